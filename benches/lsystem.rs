@@ -0,0 +1,34 @@
+// Times derivation of a few L-systems at a few step counts.
+// Run with `cargo bench --bench lsystem`.
+//
+// input/system.txt only reaches a few hundred modules, so its time is mostly spent evaluating successor expressions. The
+// branching system grows to hundreds of thousands of modules, so it shows the cost of storing and copying the string as well.
+// To compare a change against its baseline, run this on both commits on the same machine, as the times depend on it.
+
+use std::time::{Duration, Instant};
+
+use realm::lsystem::LSystem;
+
+// Each A makes three more, so the string roughly triples each step.
+const BRANCHING: &str = "#axiom A(1)\nA(s) => F(s) [ + A(s*0.7) ] [ - A(s*0.7) ] A(s*0.9)";
+
+fn bench(name: &str, source: &str, steps: &[usize], iterations: u32) {
+    for &steps in steps {
+        let mut total = Duration::ZERO;
+        let mut length = 0;
+        for _ in 0..iterations {
+            let system = realm::syntax::parse_string(realm::syntax::lsystem::system(), source).expect("failed to parse LSystem");
+            let mut lsystem = LSystem::new(system).expect("failed to resolve LSystem");
+            let start = Instant::now();
+            lsystem.start().and_then(|_| lsystem.step_by(steps)).expect("failed to derive LSystem");
+            total += start.elapsed();
+            length = lsystem.current_string().len();
+        }
+        println!("{} {:>3} steps: {:>10.3?} per derivation ({} modules)", name, steps, total / iterations, length);
+    }
+}
+
+fn main() {
+    bench("system.txt", include_str!("../input/system.txt"), &[10, 15, 20, 25], 50);
+    bench("branching ", BRANCHING, &[6, 8, 10], 5);
+}
@@ -0,0 +1,106 @@
+// Derives an L-system file without opening a window, so plant files can be iterated on without a GPU.
+// Prints the derived string, or with --stats the string length after each step and how many modules of each symbol it ends with.
+// Run with `cargo run --bin realm-lsystem -- input/system.txt 10 --stats`.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::process::exit;
+
+use realm::lsystem::LSystem;
+use realm::mesh_io::write_obj;
+use realm::syntax::lsystem::{LSystemError, System};
+
+const USAGE: &str = "usage: realm-lsystem <file> <steps> [--seed <n>] [--stats] [--obj <path>]";
+
+struct Options {
+    path: String,
+    steps: usize,
+    seed: Option<u64>,
+    stats: bool,
+    obj: Option<String>,
+}
+
+fn parse_options() -> Result<Options, String> {
+    let mut args = std::env::args().skip(1);
+    let mut positional = Vec::new();
+    let mut seed = None;
+    let mut stats = false;
+    let mut obj = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seed" => {
+                let value = args.next().ok_or("--seed needs a value")?;
+                seed = Some(value.parse().map_err(|_| format!("invalid seed: {}", value))?);
+            }
+            "--stats" => stats = true,
+            "--obj" => obj = Some(args.next().ok_or("--obj needs a path")?),
+            _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+            _ => positional.push(arg),
+        }
+    }
+    if positional.len() != 2 {
+        return Err(USAGE.to_string());
+    }
+    let steps = positional[1].parse().map_err(|_| format!("invalid step count: {}", positional[1]))?;
+    Ok(Options { path: positional.swap_remove(0), steps, seed, stats, obj })
+}
+
+fn derive(options: &Options, system: System) -> Result<LSystem, LSystemError> {
+    let mut lsystem = LSystem::new(system)?;
+    if let Some(seed) = options.seed {
+        lsystem.set_seed(seed);
+    }
+    lsystem.start()?;
+    if options.stats {
+        println!("step {}: {} modules", 0, lsystem.current_string().len());
+    }
+    for step in 1..=options.steps {
+        lsystem.step()?;
+        if options.stats {
+            println!("step {}: {} modules", step, lsystem.current_string().len());
+        }
+    }
+    Ok(lsystem)
+}
+
+fn main() {
+    let options = parse_options().unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        exit(2);
+    });
+    let system = realm::syntax::parse_lsystem_file(&options.path).unwrap_or_else(|error| {
+        eprintln!("{}: {}", options.path, error);
+        exit(1);
+    });
+
+    let lsystem = derive(&options, system).unwrap_or_else(|error| {
+        eprintln!("{}: {}", options.path, error);
+        exit(1);
+    });
+
+    if options.stats {
+        let mut counts = BTreeMap::new();
+        for module in lsystem.current_string().iter() {
+            *counts.entry(module.symbol()).or_insert(0usize) += 1;
+        }
+        for (symbol, count) in counts {
+            println!("{:>6} {}", lsystem.symbols().name(symbol), count);
+        }
+    } else {
+        println!("{}", lsystem.current_string().display(lsystem.symbols()));
+    }
+
+    if let Some(path) = options.obj {
+        let mesh = lsystem.make_mesh().unwrap_or_else(|error| {
+            eprintln!("{}: {}", options.path, error);
+            exit(1);
+        });
+        let result = File::create(&path).and_then(|file| write_obj(&mesh, BufWriter::new(file)));
+        if let Err(error) = result {
+            eprintln!("failed to write {}: {}", path, error);
+            exit(1);
+        }
+        println!("wrote {} vertices and {} triangles to {}", mesh.positions.len(), mesh.indices.len() / 3, path);
+    }
+}
@@ -0,0 +1,212 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix, Matrix4, MetricSpace, Transform as _, Vector4};
+
+use crate::transform::{Point3f, Transform};
+
+// Axis-aligned bounding box. An empty box has min greater than max, so it contains nothing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3f,
+    pub max: Point3f,
+}
+
+impl Aabb {
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Point3f::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: Point3f::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        }
+    }
+    pub fn from_points(points: impl IntoIterator<Item = Point3f>) -> Aabb {
+        let mut aabb = Aabb::empty();
+        for point in points {
+            aabb.add_point(point);
+        }
+        aabb
+    }
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+    pub fn add_point(&mut self, point: Point3f) {
+        self.min = Point3f::new(self.min.x.min(point.x), self.min.y.min(point.y), self.min.z.min(point.z));
+        self.max = Point3f::new(self.max.x.max(point.x), self.max.y.max(point.y), self.max.z.max(point.z));
+    }
+    pub fn center(&self) -> Point3f {
+        self.min.midpoint(self.max)
+    }
+    // The box around this one's transformed corners, which is larger than the box around the transformed points if it's rotated.
+    pub fn transform(&self, transform: Transform) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+        Aabb::from_points((0..8).map(|corner| transform.transform_point(Point3f::new(
+            if corner & 1 == 0 { self.min.x } else { self.max.x },
+            if corner & 2 == 0 { self.min.y } else { self.max.y },
+            if corner & 4 == 0 { self.min.z } else { self.max.z },
+        ))))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Point3f,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    // Centered on the points' bounding box, which is close to the smallest sphere for the meshes we generate and much cheaper to find.
+    pub fn from_points(points: &[Point3f]) -> BoundingSphere {
+        let aabb = Aabb::from_points(points.iter().copied());
+        if aabb.is_empty() {
+            return BoundingSphere { center: Point3f::origin(), radius: 0.0 };
+        }
+        let center = aabb.center();
+        let radius = points.iter().map(|point| center.distance(*point)).fold(0.0, f32::max);
+        BoundingSphere { center, radius }
+    }
+    pub fn transform(&self, transform: Transform) -> BoundingSphere {
+        BoundingSphere {
+            center: transform.transform_point(self.center),
+            radius: self.radius * transform.scale.abs(),
+        }
+    }
+}
+
+// The six planes bounding what a camera can see, with normals pointing inwards. A point p is inside plane (a, b, c, d) if ap.x + bp.y + cp.z + d >= 0.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    // Extracts the planes from a world to clip space matrix (Gribb and Hartmann's method). The near plane is taken at z = -w, as with
+    // cgmath::perspective. Vulkan clips at z = 0, which is in front of that, so this never culls anything that would be drawn.
+    pub fn from_matrix(world_to_clip: Matrix4<f32>) -> Frustum {
+        let row = |i| world_to_clip.row(i);
+        let planes = [row(3) + row(0), row(3) - row(0), row(3) + row(1), row(3) - row(1), row(3) + row(2), row(3) - row(2)];
+        Frustum {
+            planes: planes.map(|plane| plane / plane.truncate().magnitude()),
+        }
+    }
+
+    fn distance(plane: Vector4<f32>, point: Point3f) -> f32 {
+        plane.truncate().dot(point.to_vec()) + plane.w
+    }
+
+    pub fn contains_point(&self, point: Point3f) -> bool {
+        self.planes.iter().all(|plane| Frustum::distance(*plane, point) >= 0.0)
+    }
+    // Conservative: spheres just outside a corner of the frustum may be reported as intersecting it.
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes.iter().all(|plane| Frustum::distance(*plane, sphere.center) >= -sphere.radius)
+    }
+    // Conservative in the same way as intersects_sphere.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        !aabb.is_empty() && self.planes.iter().all(|plane| {
+            // The corner furthest along the plane's normal.
+            let corner = Point3f::new(
+                if plane.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+                if plane.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+                if plane.z >= 0.0 { aabb.max.z } else { aabb.min.z },
+            );
+            Frustum::distance(*plane, corner) >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Matrix4, Rad, Rotation3, SquareMatrix};
+
+    use super::*;
+    use crate::transform::{Quaternion, TransformExtensions, Vector3f};
+
+    // A camera at (0, 0, 10) looking down -z, with the projection CameraSystem uses.
+    fn camera_frustum() -> Frustum {
+        let proj = cgmath::perspective(Rad(std::f32::consts::FRAC_PI_2), 1.0, 0.01, 100.0);
+        let view = Matrix4::from(Transform::from_translation(Vector3f::new(0.0, 0.0, 10.0))).invert().unwrap();
+        Frustum::from_matrix(proj * view)
+    }
+
+    fn sphere(x: f32, y: f32, z: f32, radius: f32) -> BoundingSphere {
+        BoundingSphere { center: Point3f::new(x, y, z), radius }
+    }
+
+    #[test]
+    fn frustum_contains_what_is_in_front_of_the_camera() {
+        let frustum = camera_frustum();
+        assert!(frustum.intersects_sphere(&sphere(0.0, 0.0, 0.0, 1.0)));
+        assert!(frustum.contains_point(Point3f::new(0.0, 0.0, 0.0)));
+        // The field of view is 90 degrees, so 10 units ahead it reaches 10 units to the side.
+        assert!(frustum.contains_point(Point3f::new(9.0, 0.0, 0.0)));
+        assert!(!frustum.contains_point(Point3f::new(11.0, 0.0, 0.0)));
+        // Partly inside.
+        assert!(frustum.intersects_sphere(&sphere(10.5, 0.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn frustum_excludes_what_is_behind_the_camera_or_past_the_far_plane() {
+        let frustum = camera_frustum();
+        assert!(!frustum.intersects_sphere(&sphere(0.0, 0.0, 20.0, 1.0)));
+        assert!(!frustum.contains_point(Point3f::new(0.0, 0.0, 11.0)));
+        assert!(!frustum.intersects_sphere(&sphere(0.0, 0.0, -200.0, 1.0)));
+        assert!(!frustum.contains_point(Point3f::new(0.0, 0.0, -95.0)));
+        assert!(!frustum.intersects_sphere(&sphere(20.0, 0.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn frustum_intersects_aabbs_overlapping_it() {
+        let frustum = camera_frustum();
+        let aabb = |min: [f32; 3], max: [f32; 3]| Aabb { min: Point3f::from(min), max: Point3f::from(max) };
+        assert!(frustum.intersects_aabb(&aabb([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0])));
+        // Straddling the side plane.
+        assert!(frustum.intersects_aabb(&aabb([9.0, -1.0, -1.0], [12.0, 1.0, 1.0])));
+        assert!(!frustum.intersects_aabb(&aabb([-1.0, -1.0, 15.0], [1.0, 1.0, 20.0])));
+        assert!(!frustum.intersects_aabb(&aabb([-1.0, -1.0, -300.0], [1.0, 1.0, -200.0])));
+        assert!(!frustum.intersects_aabb(&Aabb::empty()));
+    }
+
+    #[test]
+    fn aabb_from_points() {
+        let aabb = Aabb::from_points([Point3f::new(1.0, -2.0, 3.0), Point3f::new(-1.0, 4.0, 0.0), Point3f::new(0.0, 0.0, 5.0)]);
+        assert_eq!(aabb, Aabb { min: Point3f::new(-1.0, -2.0, 0.0), max: Point3f::new(1.0, 4.0, 5.0) });
+        assert!(!aabb.is_empty());
+        assert_eq!(aabb.center(), Point3f::new(0.0, 1.0, 2.5));
+
+        let point = Aabb::from_points([Point3f::new(2.0, 2.0, 2.0)]);
+        assert!(!point.is_empty());
+        assert_eq!(point.min, point.max);
+    }
+
+    #[test]
+    fn aabb_from_no_points_is_empty() {
+        let aabb = Aabb::from_points(std::iter::empty());
+        assert!(aabb.is_empty());
+        assert_eq!(aabb, Aabb::empty());
+        assert!(aabb.transform(Transform::from_translation(Vector3f::new(1.0, 2.0, 3.0))).is_empty());
+        assert_eq!(BoundingSphere::from_points(&[]), sphere(0.0, 0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn aabb_transform_bounds_the_transformed_corners() {
+        let aabb = Aabb { min: Point3f::new(0.0, 0.0, 0.0), max: Point3f::new(2.0, 1.0, 1.0) };
+        let moved = aabb.transform(Transform::new(Vector3f::new(1.0, 2.0, 3.0), Quaternion::from_angle_y(Deg(0.0)), 2.0));
+        assert_eq!(moved, Aabb { min: Point3f::new(1.0, 2.0, 3.0), max: Point3f::new(5.0, 4.0, 5.0) });
+
+        // A quarter turn about y takes x to -z.
+        let turned = aabb.transform(Transform::from_rotation(Quaternion::from_angle_y(Deg(90.0))));
+        let expected = Aabb { min: Point3f::new(0.0, 0.0, -2.0), max: Point3f::new(1.0, 1.0, 0.0) };
+        assert!((turned.min - expected.min).magnitude() < 1e-5 && (turned.max - expected.max).magnitude() < 1e-5, "{:?}", turned);
+    }
+
+    #[test]
+    fn bounding_sphere_transform() {
+        let bounds = BoundingSphere::from_points(&[Point3f::new(-1.0, 0.0, 0.0), Point3f::new(3.0, 0.0, 0.0)]);
+        assert_eq!(bounds, sphere(1.0, 0.0, 0.0, 2.0));
+
+        let transform = Transform::new(Vector3f::new(0.0, 5.0, 0.0), Quaternion::from_angle_z(Deg(90.0)), -3.0);
+        let transformed = bounds.transform(transform);
+        // Rotated to (0, 1, 0), scaled to (0, -3, 0), then moved up 5. A negative scale still gives a positive radius.
+        assert!((transformed.center - Point3f::new(0.0, 2.0, 0.0)).magnitude() < 1e-5, "{:?}", transformed);
+        assert_eq!(transformed.radius, 6.0);
+    }
+}
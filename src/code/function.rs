@@ -1,8 +1,9 @@
 use std::sync::Arc;
 
+use cgmath::{Deg, Euler};
 use combine::stream::position::SourcePosition;
 
-use crate::{syntax::code::{List, SourceError}, code::spell::SpellTarget};
+use crate::{syntax::code::{List, SourceError}, code::spell::SpellTarget, mesh_io::HeightScale, noise::HeightGenerator, transform::Vector3f, world::components::motion::Motion};
 use super::{Value, ValueResult, spell::*, VariableScope, Evaluable};
 
 pub fn call_function(scope: VariableScope, list: &List, position: SourcePosition) -> ValueResult {
@@ -15,10 +16,47 @@ pub fn call_function(scope: VariableScope, list: &List, position: SourcePosition
         "bind" => bind(scope, list, position),
         "target_self" => target_self(scope, list, position),
         "create_terrain" => create_terrain(scope, list, position),
+        "create_volume" => create_volume(scope, list, position),
+        "dig" => sculpt_effect(scope, list, position, false),
+        "fill" => sculpt_effect(scope, list, position, true),
+        "position" => position_value(scope, list, position),
+        "move" => move_effect(scope, list, position),
+        "rotate" => rotate_effect(scope, list, position),
+        "scale" => scale_effect(scope, list, position),
+        "teleport" => teleport_effect(scope, list, position),
+        "export_terrain" => export_terrain(scope, list, position),
+        "load_heightmap" => load_heightmap(scope, list, position),
+        "save_heightmap" => save_heightmap(scope, list, position),
+        "create_plant" => create_plant(scope, list, position),
+        "export_plants" => export_plants(scope, list, position),
+        "fbm" => noise_generator(scope, list, position, false),
+        "ridged" => noise_generator(scope, list, position, true),
+        "terrace" => terrace(scope, list, position),
         _ => Err(SourceError::unknown_function(list[0].source_position(), function)),
     }
 }
 
+fn optional_number(list: &List, index: usize, default: f64) -> Result<f64, SourceError> {
+    if list.len() > index {
+        list.argument(index).into_number()
+    } else {
+        Ok(default)
+    }
+}
+
+fn position_argument(scope: VariableScope, list: &List, index: usize) -> Result<Vector3f, SourceError> {
+    list.argument(index).evaluate(scope).and_then(|(arg, val)| {
+        val.into_position().ok_or_else(|| SourceError::unexpected_value(&arg, "Position", &val))
+    })
+}
+
+fn generator_argument(scope: VariableScope, list: &List, index: usize) -> Result<HeightGenerator, SourceError> {
+    list.argument(index).evaluate(scope).and_then(|(arg, val)| match val {
+        Value::HeightGenerator(generator) => Ok(generator),
+        _ => Err(SourceError::unexpected_value(&arg, "HeightGenerator", &val)),
+    })
+}
+
 fn spell(scope: VariableScope, list: &List, position: SourcePosition) -> ValueResult {
     if list.len() < 3 {
         return Err(SourceError::not_enough_arguments(position, list, 3));
@@ -50,11 +88,161 @@ fn target_self(_scope: VariableScope, _list: &List, _position: SourcePosition) -
     Ok(Value::SpellTarget(SpellTarget::Myself))
 }
 
-fn create_terrain(_scope: VariableScope, list: &List, position: SourcePosition) -> ValueResult {
+fn create_terrain(scope: VariableScope, list: &List, position: SourcePosition) -> ValueResult {
     if list.len() < 3 {
         return Err(SourceError::not_enough_arguments(position, list, 3));
     }
     let w = list.argument(1).into_number()?;
     let h = list.argument(2).into_number()?;
-    Ok(Value::SpellEffect(Box::new(CreateTerrainEffect(w as u32, h as u32))))
+    let generator = if list.len() > 3 { Some(generator_argument(scope, list, 3)?) } else { None };
+    Ok(Value::SpellEffect(Box::new(CreateTerrainEffect(w as u32, h as u32, generator))))
+}
+
+// (fbm seed octaves frequency amplitude), and the same for ridged.
+fn noise_generator(_scope: VariableScope, list: &List, position: SourcePosition, ridged: bool) -> ValueResult {
+    if list.len() < 5 {
+        return Err(SourceError::not_enough_arguments(position, list, 5));
+    }
+    let seed = list.argument(1).into_number()? as u64;
+    let octaves = list.argument(2).into_number()? as u32;
+    let frequency = list.argument(3).into_number()?;
+    let amplitude = list.argument(4).into_number()?;
+    Ok(Value::HeightGenerator(if ridged {
+        HeightGenerator::Ridged { seed, octaves, frequency, amplitude }
+    } else {
+        HeightGenerator::Fbm { seed, octaves, frequency, amplitude }
+    }))
+}
+
+fn terrace(scope: VariableScope, list: &List, position: SourcePosition) -> ValueResult {
+    if list.len() < 3 {
+        return Err(SourceError::not_enough_arguments(position, list, 3));
+    }
+    let source = generator_argument(scope, list, 1)?;
+    let step = list.argument(2).into_number()?;
+    Ok(Value::HeightGenerator(HeightGenerator::Terrace { source: Box::new(source), step }))
+}
+
+// A volume needs at least two samples along each axis to have any cells for a surface to pass through.
+fn volume_size(list: &List, index: usize) -> Result<u32, SourceError> {
+    let argument = list.argument(index);
+    let size = argument.into_number()?;
+    if size < 2.0 {
+        return Err(SourceError::unexpected_term(&argument, "size of at least 2", format!("`{}`", size)));
+    }
+    Ok(size as u32)
+}
+
+fn create_volume(_scope: VariableScope, list: &List, position: SourcePosition) -> ValueResult {
+    if list.len() < 4 {
+        return Err(SourceError::not_enough_arguments(position, list, 4));
+    }
+    let (w, h, d) = (volume_size(list, 1)?, volume_size(list, 2)?, volume_size(list, 3)?);
+    Ok(Value::SpellEffect(Box::new(CreateVolumeEffect(w, h, d))))
+}
+
+fn sculpt_effect(_scope: VariableScope, list: &List, position: SourcePosition, fill: bool) -> ValueResult {
+    if list.len() < 2 {
+        return Err(SourceError::not_enough_arguments(position, list, 2));
+    }
+    let radius = list.argument(1).into_number()?;
+    Ok(Value::SpellEffect(Box::new(SculptEffect { radius: radius as f32, fill })))
+}
+
+fn position_value(_scope: VariableScope, list: &List, position: SourcePosition) -> ValueResult {
+    if list.len() < 4 {
+        return Err(SourceError::not_enough_arguments(position, list, 4));
+    }
+    let x = list.argument(1).into_number()?;
+    let y = list.argument(2).into_number()?;
+    let z = list.argument(3).into_number()?;
+    Ok(Value::Position(Vector3f::new(x as f32, y as f32, z as f32)))
+}
+
+fn move_effect(scope: VariableScope, list: &List, position: SourcePosition) -> ValueResult {
+    if list.len() < 2 {
+        return Err(SourceError::not_enough_arguments(position, list, 2));
+    }
+    let offset = position_argument(scope, list, 1)?;
+    let duration = optional_number(list, 2, 0.0)?;
+    Ok(Value::SpellEffect(Box::new(MotionEffect { motion: Motion::Translate(offset), duration })))
+}
+
+fn rotate_effect(_scope: VariableScope, list: &List, position: SourcePosition) -> ValueResult {
+    if list.len() < 4 {
+        return Err(SourceError::not_enough_arguments(position, list, 4));
+    }
+    let yaw = list.argument(1).into_number()?;
+    let pitch = list.argument(2).into_number()?;
+    let roll = list.argument(3).into_number()?;
+    let duration = optional_number(list, 4, 0.0)?;
+    let angles = Euler::new(Deg(pitch as f32), Deg(yaw as f32), Deg(roll as f32));
+    Ok(Value::SpellEffect(Box::new(MotionEffect { motion: Motion::Rotate(angles), duration })))
+}
+
+fn scale_effect(_scope: VariableScope, list: &List, position: SourcePosition) -> ValueResult {
+    if list.len() < 2 {
+        return Err(SourceError::not_enough_arguments(position, list, 2));
+    }
+    let factor = list.argument(1).into_number()?;
+    let duration = optional_number(list, 2, 0.0)?;
+    Ok(Value::SpellEffect(Box::new(MotionEffect { motion: Motion::Scale(factor as f32), duration })))
+}
+
+fn teleport_effect(scope: VariableScope, list: &List, position: SourcePosition) -> ValueResult {
+    if list.len() < 2 {
+        return Err(SourceError::not_enough_arguments(position, list, 2));
+    }
+    let destination = position_argument(scope, list, 1)?;
+    let yaw = if list.len() > 2 { Some(Deg(list.argument(2).into_number()? as f32)) } else { None };
+    Ok(Value::SpellEffect(Box::new(TeleportEffect { position: destination, yaw })))
+}
+
+fn export_terrain(_scope: VariableScope, list: &List, position: SourcePosition) -> ValueResult {
+    if list.len() < 2 {
+        return Err(SourceError::not_enough_arguments(position, list, 2));
+    }
+    let path = list.argument(1).into_literal()?;
+    Ok(Value::SpellEffect(Box::new(ExportTerrainEffect(path.into()))))
+}
+
+// Heightmap spells take the file's path, then an optional scale and offset.
+fn height_scale(list: &List) -> Result<HeightScale, SourceError> {
+    let default = HeightScale::default();
+    Ok(HeightScale {
+        scale: optional_number(list, 2, default.scale as f64)? as f32,
+        offset: optional_number(list, 3, default.offset as f64)? as f32,
+    })
+}
+
+fn load_heightmap(_scope: VariableScope, list: &List, position: SourcePosition) -> ValueResult {
+    if list.len() < 2 {
+        return Err(SourceError::not_enough_arguments(position, list, 2));
+    }
+    let path = list.argument(1).into_literal()?;
+    Ok(Value::SpellEffect(Box::new(LoadHeightmapEffect { path: path.into(), scale: height_scale(list)? })))
+}
+
+fn save_heightmap(_scope: VariableScope, list: &List, position: SourcePosition) -> ValueResult {
+    if list.len() < 2 {
+        return Err(SourceError::not_enough_arguments(position, list, 2));
+    }
+    let path = list.argument(1).into_literal()?;
+    Ok(Value::SpellEffect(Box::new(SaveHeightmapEffect { path: path.into(), scale: height_scale(list)? })))
+}
+
+fn create_plant(_scope: VariableScope, list: &List, position: SourcePosition) -> ValueResult {
+    if list.len() < 2 {
+        return Err(SourceError::not_enough_arguments(position, list, 2));
+    }
+    let path = list.argument(1).into_literal()?;
+    Ok(Value::SpellEffect(Box::new(CreatePlantEffect(path.into()))))
+}
+
+fn export_plants(_scope: VariableScope, list: &List, position: SourcePosition) -> ValueResult {
+    if list.len() < 2 {
+        return Err(SourceError::not_enough_arguments(position, list, 2));
+    }
+    let path = list.argument(1).into_literal()?;
+    Ok(Value::SpellEffect(Box::new(ExportPlantsEffect(path.into()))))
 }
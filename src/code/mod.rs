@@ -3,7 +3,7 @@ pub mod spell;
 
 use std::{collections::HashMap, sync::Arc};
 
-use crate::{transform::{Vector3f, Transform, TransformExtensions}, world::components::DrawableId, syntax::code::{ListTerm, SourceListTerm, SourceListArgument, SourceError}};
+use crate::{transform::{Vector3f, Transform, TransformExtensions}, noise::HeightGenerator, world::components::DrawableId, syntax::code::{ListTerm, SourceListTerm, SourceListArgument, SourceError}};
 use spell::*;
 
 pub type Variable = String;
@@ -52,6 +52,7 @@ pub enum Value {
     SpellEffect(Box<dyn SpellEffect>),
     Spell(Arc<Spell>),
     SpellBinding(u8, Arc<Spell>),
+    HeightGenerator(HeightGenerator),
 }
 
 impl Value {
@@ -65,6 +66,7 @@ impl Value {
             Value::SpellEffect(_) => "SpellEffect",
             Value::Spell(_) => "Spell",
             Value::SpellBinding(_, _) => "SpellBinding",
+            Value::HeightGenerator(_) => "HeightGenerator",
         }
     }
     fn into_number(&self) -> Option<f64> {
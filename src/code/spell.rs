@@ -1,6 +1,6 @@
-use cgmath::EuclideanSpace;
+use cgmath::{Deg, EuclideanSpace, Euler, Quaternion};
 
-use crate::{world::components::terrain::TerrainPatch, world::spellcaster::SpellContext, transform::{Point3f, Transform, TransformExtensions}, triangle_draw::TriangleDrawable};
+use crate::{lsystem::LSystem, mesh_generation::GenerateMesh, mesh_io::{HeightScale, load_heightmap, save_heightmap}, noise::HeightGenerator, world::components::{terrain::TerrainPatch, volume::VolumePatch, motion::{Motion, MotionComponent}, plant::PlantComponent}, world::spellcaster::SpellContext, transform::{Point3f, Vector3f, Transform, TransformExtensions, QuaternionExtensions}, triangle_draw::TriangleDrawable};
 
 use super::EntityId;
 
@@ -33,8 +33,9 @@ pub struct Spell {
     pub effect: Box<dyn SpellEffect>,
 }
 
+// A patch of width by depth heights, centered on the target. Without a generator it's flat, at the target's height.
 #[derive(Debug)]
-pub struct CreateTerrainEffect(pub u32, pub u32);
+pub struct CreateTerrainEffect(pub u32, pub u32, pub Option<HeightGenerator>);
 
 impl SpellEffect for CreateTerrainEffect {
     fn apply(&self, context: &mut SpellContext, targets: &[ResolvedTarget]) {
@@ -44,8 +45,214 @@ impl SpellEffect for CreateTerrainEffect {
         transform.disp.z -= self.1 as f32 / 2.0;
         let terrain = context.components.drawables.add(TriangleDrawable {
             meshes: Vec::new(),
+            lods: Vec::new(),
             transform,
         });
-        context.components.terrain.add(TerrainPatch::new(terrain, [self.0, self.1]));
+        let mut patch = TerrainPatch::new(terrain, [self.0, self.1]);
+        if let Some(generator) = &self.2 {
+            patch.generate_heights(generator, transform);
+        }
+        context.components.terrain.add(patch);
+    }
+}
+
+// A volume of width, height and depth voxels, centered on the target, which is level with its ground.
+#[derive(Debug)]
+pub struct CreateVolumeEffect(pub u32, pub u32, pub u32);
+
+impl SpellEffect for CreateVolumeEffect {
+    fn apply(&self, context: &mut SpellContext, targets: &[ResolvedTarget]) {
+        assert!(!targets.is_empty(), "create_volume effect requires a target");
+        let ground = (self.1 / 2) as f32;
+        let mut transform: Transform = targets[0].clone().into();
+        transform.disp.x -= self.0 as f32 / 2.0;
+        transform.disp.y -= ground;
+        transform.disp.z -= self.2 as f32 / 2.0;
+        let volume = context.components.drawables.add(TriangleDrawable {
+            meshes: Vec::new(),
+            lods: Vec::new(),
+            transform,
+        });
+        context.components.volumes.add(VolumePatch::new(volume, [self.0, self.1, self.2], ground));
+    }
+}
+
+// Digs out (or fills in) a sphere around each target in every volume it touches.
+#[derive(Debug)]
+pub struct SculptEffect {
+    pub radius: f32,
+    pub fill: bool,
+}
+
+impl SpellEffect for SculptEffect {
+    fn apply(&self, context: &mut SpellContext, targets: &[ResolvedTarget]) {
+        for target in targets {
+            context.components.volumes.sculpt_sphere(&context.components.drawables, target.position, self.radius, self.fill);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MotionEffect {
+    pub motion: Motion,
+    pub duration: f64,
+}
+
+impl SpellEffect for MotionEffect {
+    fn apply(&self, context: &mut SpellContext, targets: &[ResolvedTarget]) {
+        for target in targets {
+            if self.duration > 0.0 {
+                context.components.motions.add(MotionComponent::new(target.entity, self.motion, self.duration));
+            } else {
+                self.motion.apply(target.entity, &mut context.components.drawables, &mut context.components.avatars);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TeleportEffect {
+    pub position: Vector3f,
+    pub yaw: Option<Deg<f32>>,
+}
+
+impl SpellEffect for TeleportEffect {
+    fn apply(&self, context: &mut SpellContext, targets: &[ResolvedTarget]) {
+        for target in targets {
+            let transform = match context.components.drawables.get_mut(target.entity) {
+                Some(drawable) => &mut drawable.transform,
+                None => continue,
+            };
+            transform.disp = self.position;
+            if let Some(yaw) = self.yaw {
+                let angles = Euler::new(Deg(0.0), yaw, Deg(0.0));
+                if let Some(avatar) = context.components.avatars.find_by_parent_mut(target.entity) {
+                    avatar.set_rotation(transform, angles);
+                } else {
+                    transform.rot = Quaternion::from_euler_yxz(angles);
+                }
+            }
+        }
+    }
+}
+
+// The path to save the component at `index` to, when saving several. The first gets the path as given, and the others have their index added.
+fn numbered_path(path: &std::path::Path, index: usize) -> std::path::PathBuf {
+    let mut path = path.to_path_buf();
+    if index > 0 {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        let extension = path.extension().unwrap_or_default().to_string_lossy().into_owned();
+        path.set_file_name(format!("{}_{}.{}", stem, index, extension));
+    }
+    path
+}
+
+#[derive(Debug)]
+pub struct ExportTerrainEffect(pub std::path::PathBuf);
+
+impl SpellEffect for ExportTerrainEffect {
+    fn apply(&self, context: &mut SpellContext, _targets: &[ResolvedTarget]) {
+        for (index, terrain) in context.components.terrain.iter().enumerate() {
+            let path = numbered_path(&self.0, index);
+            if let Err(error) = terrain.export_mesh(&path) {
+                println!("Failed to export terrain to {}: {}", path.display(), error);
+            }
+        }
+    }
+}
+
+// Creates a terrain patch from a heightmap file, centered on the target.
+#[derive(Debug)]
+pub struct LoadHeightmapEffect {
+    pub path: std::path::PathBuf,
+    pub scale: HeightScale,
+}
+
+impl SpellEffect for LoadHeightmapEffect {
+    fn apply(&self, context: &mut SpellContext, targets: &[ResolvedTarget]) {
+        assert!(!targets.is_empty(), "load_heightmap effect requires a target");
+        let heightmap = match load_heightmap(&self.path, self.scale) {
+            Ok(heightmap) if heightmap.size.iter().all(|size| *size >= 2) => heightmap,
+            Ok(_) => {
+                println!("Heightmap {} is too small to make terrain from", self.path.display());
+                return;
+            }
+            Err(error) => {
+                println!("Failed to load heightmap from {}: {}", self.path.display(), error);
+                return;
+            }
+        };
+        let mut transform: Transform = targets[0].clone().into();
+        transform.disp.x -= heightmap.size[0] as f32 / 2.0;
+        transform.disp.z -= heightmap.size[1] as f32 / 2.0;
+        let terrain = context.components.drawables.add(TriangleDrawable {
+            meshes: Vec::new(),
+            lods: Vec::new(),
+            transform,
+        });
+        context.components.terrain.add(TerrainPatch::from_heightmap(terrain, heightmap));
+    }
+}
+
+// Saves the heights of every terrain patch, naming the files like export_terrain.
+#[derive(Debug)]
+pub struct SaveHeightmapEffect {
+    pub path: std::path::PathBuf,
+    pub scale: HeightScale,
+}
+
+impl SpellEffect for SaveHeightmapEffect {
+    fn apply(&self, context: &mut SpellContext, _targets: &[ResolvedTarget]) {
+        for (index, terrain) in context.components.terrain.iter().enumerate() {
+            let path = numbered_path(&self.path, index);
+            match save_heightmap(&terrain.heightmap(), &path, self.scale) {
+                Ok(0) => {}
+                Ok(clamped) => println!("Saved heightmap to {}, but {} heights were outside the scale and offset's range and were clamped", path.display(), clamped),
+                Err(error) => println!("Failed to save heightmap to {}: {}", path.display(), error),
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CreatePlantEffect(pub std::path::PathBuf);
+
+impl SpellEffect for CreatePlantEffect {
+    fn apply(&self, context: &mut SpellContext, targets: &[ResolvedTarget]) {
+        assert!(!targets.is_empty(), "create_plant effect requires a target");
+        let system = match crate::syntax::parse_lsystem_file(&self.0) {
+            Ok(system) => system,
+            Err(error) => {
+                println!("{}", error);
+                return;
+            }
+        };
+        let plant = LSystem::new(system).and_then(|mut lsystem| {
+            lsystem.load_surfaces(|name| context.assets.get_surface(name).cloned());
+            PlantComponent::new(context.components.drawables.add(TriangleDrawable {
+                meshes: Vec::new(),
+                lods: Vec::new(),
+                transform: targets[0].clone().into(),
+            }), lsystem)
+        });
+        match plant {
+            Ok(plant) => { context.components.plants.add(plant); }
+            Err(error) => println!("{}", error),
+        }
+    }
+}
+
+// Exports the mesh of every plant as it is now, naming the files like export_terrain.
+#[derive(Debug)]
+pub struct ExportPlantsEffect(pub std::path::PathBuf);
+
+impl SpellEffect for ExportPlantsEffect {
+    fn apply(&self, context: &mut SpellContext, _targets: &[ResolvedTarget]) {
+        for (index, plant) in context.components.plants.iter().enumerate() {
+            let path = numbered_path(&self.0, index);
+            if let Err(error) = plant.lsystem().export_mesh(&path) {
+                println!("Failed to export plant to {}: {}", path.display(), error);
+            }
+        }
     }
 }
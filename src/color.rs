@@ -1,12 +1,18 @@
 // TODO use a color library (palette?)
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub struct Color([f32; 4]);
 
 impl Color {
     pub fn rgb(r: f32, g: f32, b: f32) -> Color {
         Color([r, g, b, 1.0])
     }
+    pub fn rgba(r: f32, g: f32, b: f32, a: f32) -> Color {
+        Color([r, g, b, a])
+    }
+    pub fn white() -> Color {
+        Color([1.0, 1.0, 1.0, 1.0])
+    }
 }
 
 impl From<Color> for [f32; 4] {
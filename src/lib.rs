@@ -0,0 +1,12 @@
+pub mod bounds;
+pub mod color;
+pub mod code;
+pub mod frame;
+pub mod mesh_generation;
+pub mod mesh_io;
+pub mod noise;
+pub mod syntax;
+pub mod lsystem;
+pub mod triangle_draw;
+pub mod transform;
+pub mod world;
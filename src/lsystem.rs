@@ -1,56 +1,90 @@
-use cgmath::{Deg, EuclideanSpace, InnerSpace, Rotation, Rotation3, Zero};
+use std::collections::HashMap;
 
-use crate::code::{VariableScope, VariableMap, Evaluable};
+use cgmath::{Deg, EuclideanSpace, InnerSpace, Matrix3, Rad, Rotation, Rotation3, Transform as _};
+
+use crate::code::VariableScope;
+use crate::color::Color;
 use crate::transform::{Transform, TransformExtensions, Quaternion, Point3f, Vector3f};
 use crate::syntax::lsystem::*;
+use crate::mesh_generation::{polygon_normal, triangulate_polygon};
 use crate::triangle_draw::TriangleMesh;
 
 // Constant symbols
-// F    Move forward some distance, drawing a line.
+// Symbols that take an angle or distance use the system's #angle or #step when written without parameters.
+// F    Move forward some distance, drawing a segment. Bent towards the #tropism vector afterwards.
 // f    Move forward some distance without drawing a line.
 // +    Turn by some angle.
+// -    Turn by some angle in the opposite direction.
 // &    Pitch by some angle.
+// ^    Pitch by some angle in the opposite direction.
 // /    Roll by some angle.
+// \    Roll by some angle in the opposite direction.
 // |    Turn around.
+// $    Roll so the turtle's left vector is horizontal.
+// !    Set the width of segments drawn by F.
+// '    Set the color index into the system's palette, or increment it if there is no parameter.
 // [    Push the current state of the turtle onto the stack.
 // ]    Pop a state from the stack and make it the current state of the turtle.
-// {    Begin a polygon.
+// {    Begin a polygon. Its front face is the side the turtle's up vector points to.
 // }    End a polygon.
 // .    Emit a vertex (only valid inside {}).
 // G    Same as f, but for use inside {}.
+// ~    Draw a #surface at the turtle's position and orientation. Takes the surface's name and a scale (default 1).
+//      Uses the surface's vertex colors if it has them, otherwise the current color.
 
-pub struct LSymbol {
-    symbol: char,
-    params: Vec<f64>,
+// Query modules
+// Their parameters are overwritten by LSystem::query, so the productions of the next step can respond to them.
+// ?P(x,y,z)    The turtle's position.
+// ?H(x,y,z)    The turtle's heading.
+// ?U(x,y,z)    The turtle's up vector.
+// ?L(x,y,z)    The turtle's left vector.
+// ?E(...)      Communication module. Its parameters are sent to the Environment, which replaces them with its reply.
+
+#[derive(Clone, Copy)]
+pub struct LSymbol<'a> {
+    symbol: SymbolId,
+    birth: f64,
+    lifetime: f64,
+    params: &'a [f64],
 }
 
-impl LSymbol {
-    pub fn new(symbol: char) -> LSymbol {
-        LSymbol { symbol, params: Vec::new() }
+impl<'a> LSymbol<'a> {
+    pub fn symbol(&self) -> SymbolId {
+        self.symbol
     }
-    pub fn new_params(symbol: char, params: Vec<f64>) -> LSymbol {
-        LSymbol { symbol, params }
+    fn param_or(&self, index: usize, default: f64) -> f64 {
+        self.params.get(index).copied().unwrap_or(default)
+    }
+
+    // Formats the module the way it's written in a file, looking its name up in `symbols`.
+    pub fn display(self, symbols: &'a SymbolTable) -> impl std::fmt::Display + 'a {
+        DisplayModule { module: self, symbols }
     }
 }
 
-impl std::fmt::Display for LSymbol {
+struct DisplayModule<'a> {
+    module: LSymbol<'a>,
+    symbols: &'a SymbolTable,
+}
+
+impl<'a> std::fmt::Display for DisplayModule<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.symbol)?;
-        if !self.params.is_empty() {
+        write!(f, "{}", self.symbols.name(self.module.symbol))?;
+        if !self.module.params.is_empty() {
             write!(f, "(")?;
-            VecFormatter::write(f, &self.params, ", ")?;
+            IterFormatter::write(f, self.module.params.iter(), ", ")?;
             write!(f, ")")?;
         }
         Ok(())
     }
 }
 
-struct VecFormatter;
+struct IterFormatter;
 
-impl VecFormatter {
-    fn write<T: std::fmt::Display>(f: &mut std::fmt::Formatter<'_>, vec: &Vec<T>, sep: &str) -> std::fmt::Result {
+impl IterFormatter {
+    fn write<T: std::fmt::Display>(f: &mut std::fmt::Formatter<'_>, iter: impl Iterator<Item = T>, sep: &str) -> std::fmt::Result {
         let mut first = true;
-        for item in vec.iter() {
+        for item in iter {
             if first {
                 first = false;
             } else {
@@ -62,190 +96,742 @@ impl VecFormatter {
     }
 }
 
-pub struct LString(Vec<LSymbol>);
+// Modules are stored as parallel arrays instead of each owning its parameters, so building a string doesn't allocate per module.
+// The parameters of module i are params[offsets[i]..offsets[i + 1]], or params[offsets[i]..] for the last module.
+// Each module also records the derivation time it was created at, which timed L-systems use to compute its age,
+// and its lifetime: the age at which derive_to rewrites it.
+#[derive(Default)]
+pub struct LString {
+    symbols: Vec<SymbolId>,
+    births: Vec<f64>,
+    lifetimes: Vec<f64>,
+    offsets: Vec<u32>,
+    params: Vec<f64>,
+}
 
 impl LString {
-    fn new() -> LString {
-        LString(Vec::new())
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+    fn clear(&mut self) {
+        self.symbols.clear();
+        self.births.clear();
+        self.lifetimes.clear();
+        self.offsets.clear();
+        self.params.clear();
     }
-    pub fn iter(&self) -> std::slice::Iter<'_, LSymbol> {
-        self.0.iter()
+
+    pub fn push(&mut self, symbol: SymbolId, birth: f64, lifetime: f64, params: impl IntoIterator<Item = f64>) {
+        self.symbols.push(symbol);
+        self.births.push(birth);
+        self.lifetimes.push(lifetime);
+        self.offsets.push(self.params.len() as u32);
+        self.params.extend(params);
+    }
+    fn push_module(&mut self, module: LSymbol) {
+        self.push(module.symbol, module.birth, module.lifetime, module.params.iter().copied());
+    }
+    // Backdates births so every module has reached its lifetime by `time`, and is drawn fully grown.
+    fn mature(&mut self, time: f64) {
+        for (birth, lifetime) in self.births.iter_mut().zip(self.lifetimes.iter()) {
+            *birth = birth.min(time - lifetime);
+        }
+    }
+
+    pub fn get(&self, index: usize) -> LSymbol<'_> {
+        let start = self.offsets[index] as usize;
+        let end = self.offsets.get(index + 1).map_or(self.params.len(), |end| *end as usize);
+        LSymbol { symbol: self.symbols[index], birth: self.births[index], lifetime: self.lifetimes[index], params: &self.params[start..end] }
+    }
+    pub fn iter(&self) -> impl Iterator<Item = LSymbol<'_>> {
+        (0..self.len()).map(move |index| self.get(index))
+    }
+    fn params_mut(&mut self, index: usize) -> &mut [f64] {
+        let start = self.offsets[index] as usize;
+        let end = self.offsets.get(index + 1).map_or(self.params.len(), |end| *end as usize);
+        &mut self.params[start..end]
+    }
+
+    pub fn display<'a>(&'a self, symbols: &'a SymbolTable) -> impl std::fmt::Display + 'a {
+        DisplayString { string: self, symbols }
     }
 }
 
-impl std::fmt::Display for LString {
+struct DisplayString<'a> {
+    string: &'a LString,
+    symbols: &'a SymbolTable,
+}
+
+impl<'a> std::fmt::Display for DisplayString<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        VecFormatter::write(f, &self.0, " ")
+        IterFormatter::write(f, self.string.iter().map(|module| module.display(self.symbols)), " ")
+    }
+}
+
+// SplitMix64, which is plenty for choosing between stochastic productions and keeps derivation reproducible for a given seed.
+#[derive(Clone)]
+struct Random(u64);
+
+impl Random {
+    fn next_f64(&mut self) -> f64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// Limits how deeply decomposition and interpretation rules can expand a single module, in case they never stop matching.
+const MAX_REWRITE_DEPTH: usize = 64;
+
+// Productions grouped by predecessor symbol, in the order they appear in the file.
+#[derive(Default)]
+struct RuleSet(HashMap<SymbolId, Vec<ResolvedProduction>>);
+
+impl RuleSet {
+    fn new(productions: &[Production], constants: VariableScope, lifetimes: &HashMap<String, f64>, symbols: &mut SymbolTable) -> Result<RuleSet, LSystemError> {
+        let mut rules = RuleSet::default();
+        for production in productions.iter() {
+            let predecessor = symbols.intern(&production.predecessor.name);
+            rules.0.entry(predecessor).or_default().push(production.resolve(constants, lifetimes, symbols)?);
+        }
+        Ok(rules)
+    }
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn choose<'a>(productions: &'a [ResolvedProduction], module: LSymbol, random: &mut Random) -> Result<Option<&'a ResolvedProduction>, LSystemError> {
+        let mut first = None;
+        for production in productions.iter() {
+            if production.matches(module.params)? {
+                first = Some(production);
+                break;
+            }
+        }
+        let first = match first {
+            Some(production) if production.weight.is_some() => production,
+            first => return Ok(first),
+        };
+        let mut weighted = Vec::new();
+        for production in productions.iter() {
+            if let Some(weight) = production.weight {
+                if production.matches(module.params)? {
+                    weighted.push((weight, production));
+                }
+            }
+        }
+        let total: f64 = weighted.iter().map(|(weight, _)| weight).sum();
+        let mut choice = random.next_f64() * total;
+        for (weight, production) in weighted {
+            choice -= weight;
+            if choice < 0.0 {
+                return Ok(Some(production));
+            }
+        }
+        Ok(Some(first))
+    }
+
+    // Writes the successor of the first production matching `module` to `output`, with the given birth time.
+    // Returns false if no production has the module as its predecessor. A module whose productions all fail their conditions is removed.
+    fn rewrite_module(&self, module: LSymbol, birth: f64, random: &mut Random, output: &mut LString) -> Result<bool, LSystemError> {
+        let productions = match self.0.get(&module.symbol) {
+            Some(productions) => productions,
+            None => return Ok(false),
+        };
+        if let Some(production) = RuleSet::choose(productions, module, random)? {
+            for add_module in production.successor.iter() {
+                add_module.push_to(module.params, birth, output);
+            }
+        }
+        Ok(true)
+    }
+    fn rewrite_recursive(&self, module: LSymbol, depth: usize, random: &mut Random, output: &mut LString) -> Result<(), LSystemError> {
+        if depth < MAX_REWRITE_DEPTH {
+            let mut successor = LString::default();
+            if self.rewrite_module(module, module.birth, random, &mut successor)? {
+                for add_module in successor.iter() {
+                    self.rewrite_recursive(add_module, depth + 1, random, output)?;
+                }
+                return Ok(());
+            }
+        }
+        output.push_module(module);
+        Ok(())
+    }
+    fn rewrite_string(&self, string: &LString, random: &mut Random, output: &mut LString) -> Result<(), LSystemError> {
+        for (index, module) in string.iter().enumerate() {
+            self.rewrite_recursive(module, 0, random, output).map_err(|error| error.at_module(index))?;
+        }
+        Ok(())
+    }
+}
+
+// The world an open L-system grows in, which answers its ?E communication modules.
+// `params` holds the module's parameters, and is overwritten with the reply.
+pub trait Environment {
+    fn query(&mut self, position: Point3f, heading: Vector3f, params: &mut [f64]);
+}
+
+impl<F> Environment for F where F: FnMut(Point3f, Vector3f, &mut [f64]) {
+    fn query(&mut self, position: Point3f, heading: Vector3f, params: &mut [f64]) {
+        self(position, heading, params)
     }
 }
 
 pub struct LSystem {
     system: System,
+    productions: RuleSet,
+    decompositions: RuleSet,
+    interpretations: RuleSet,
+    // Names of the modules used by the system, which are stored as SymbolIds.
+    symbols: SymbolTable,
+    // Meshes of the system's #surfaces, indexed like System::surfaces. Surfaces without a string are None until load_surfaces.
+    surfaces: Vec<Option<TriangleMesh>>,
+    axiom: LString,
     string: LString,
+    // Double buffer for the string, so each step can reuse the previous step's allocations.
+    next_string: LString,
+    // Number of steps taken since start, counting each pass of derive_to as a step. Used to locate errors.
+    steps: usize,
+    time: f64,
+    seed: u64,
+    random: Random,
 }
 
 impl LSystem {
-    pub fn new(system: System) -> LSystem {
-        LSystem { system, string: LString::new() }
+    // Resolves the system's productions, axiom and surfaces. Fails if any of them use an unknown variable.
+    pub fn new(system: System) -> Result<LSystem, LSystemError> {
+        let const_scope = VariableScope::new(&system.constants);
+        let mut symbols = SymbolTable::default();
+        let lifetimes = &system.lifetimes;
+        let productions = RuleSet::new(&system.productions, const_scope, lifetimes, &mut symbols)?;
+        let decompositions = RuleSet::new(&system.decompositions, const_scope, lifetimes, &mut symbols)?;
+        let interpretations = RuleSet::new(&system.interpretations, const_scope, lifetimes, &mut symbols)?;
+        let mut axiom = LString::default();
+        for symbol in system.axiom.iter() {
+            symbol.resolve(&[], const_scope, lifetimes, &mut symbols)?.push_to(&[], 0.0, &mut axiom);
+        }
+        let mut surfaces = Vec::with_capacity(system.surfaces.len());
+        for surface in system.surfaces.iter() {
+            surfaces.push(match &surface.string {
+                Some(body) => {
+                    let mut string = LString::default();
+                    for symbol in body.iter() {
+                        symbol.resolve(&[], const_scope, lifetimes, &mut symbols)?.push_to(&[], 0.0, &mut string);
+                    }
+                    Some(TurtleInterpreter::make_surface(&string, &system.turtle)?)
+                }
+                None => None,
+            });
+        }
+        Ok(LSystem {
+            system,
+            productions,
+            decompositions,
+            interpretations,
+            symbols,
+            surfaces,
+            axiom,
+            string: LString::default(),
+            next_string: LString::default(),
+            steps: 0,
+            time: 0.0,
+            seed: 0,
+            random: Random(0),
+        })
+    }
+
+    // Seeds the choice of stochastic productions. Takes effect from the next call to start.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
     }
 
-    fn create_local_variable_map(module: &LSymbol, production: &Production) -> VariableMap {
-        let mut map = VariableMap::new();
-        if let Some(param_names) = production.predecessor.params.as_ref() {
-            let len = module.params.len().min(param_names.len());
-            for i in 0..len {
-                map.insert(param_names[i].clone(), module.params[i]);
+    // Supplies the meshes of surfaces declared without a string. Surfaces `load` returns None for aren't drawn.
+    pub fn load_surfaces(&mut self, mut load: impl FnMut(&str) -> Option<TriangleMesh>) {
+        for (surface, mesh) in self.system.surfaces.iter().zip(self.surfaces.iter_mut()) {
+            if surface.string.is_none() {
+                *mesh = load(&surface.name);
             }
         }
-        map
-    }
-
-    pub fn start(&mut self) {
-        self.string = LString(vec![LSymbol::new('0')]);
-        self.step();
-    }
-    pub fn step(&mut self) {
-        assert!(!self.string.0.is_empty());
-        let const_scope = VariableScope::new(&self.system.constants);
-        let prev_string = self.string.0.split_off(0);
-        for module in prev_string {
-            let mut found_production = false;
-            for production in self.system.productions.iter() {
-                if module.symbol != production.predecessor.symbol { continue; }
-                found_production = true;
-                let local_variables = LSystem::create_local_variable_map(&module, production);
-                let local_scope = const_scope.inner_scope(&local_variables);
-                if production.conditions.evaluate(local_scope) {
-                    for add_module in production.successor.iter() {
-                        self.string.0.push(add_module.evaluate(local_scope));
-                    }
-                    break;
+    }
+
+    fn decompose(&mut self) -> Result<(), LSystemError> {
+        if self.decompositions.is_empty() { return Ok(()); }
+        self.next_string.clear();
+        self.decompositions.rewrite_string(&self.string, &mut self.random, &mut self.next_string).map_err(|error| error.at_step(self.steps))?;
+        std::mem::swap(&mut self.string, &mut self.next_string);
+        Ok(())
+    }
+
+    pub fn start(&mut self) -> Result<(), LSystemError> {
+        self.steps = 0;
+        self.time = 0.0;
+        self.random = Random(self.seed);
+        self.string.clear();
+        for module in self.axiom.iter() {
+            self.string.push_module(module);
+        }
+        self.decompose()
+    }
+    // Rewrites every module at once, regardless of its age. Untimed derivation doesn't grow modules in, so they're all left fully grown.
+    pub fn step(&mut self) -> Result<(), LSystemError> {
+        self.steps += 1;
+        self.next_string.clear();
+        for (index, module) in self.string.iter().enumerate() {
+            let rewritten = self.productions.rewrite_module(module, self.time, &mut self.random, &mut self.next_string)
+                .map_err(|error| error.at_step(self.steps).at_module(index))?;
+            if !rewritten {
+                self.next_string.push_module(module);
+            }
+        }
+        std::mem::swap(&mut self.string, &mut self.next_string);
+        self.decompose()?;
+        self.string.mature(self.time);
+        Ok(())
+    }
+    pub fn step_by(&mut self, iterations: usize) -> Result<(), LSystemError> {
+        for _ in 0..iterations {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    // Timed derivation. Each module is rewritten once it reaches its lifetime, and its successors are born at the moment it expired,
+    // so the result doesn't depend on how time is sampled. With every lifetime at the default of 1, deriving to time n matches n steps.
+    // Returns whether any module was rewritten. Time can't go backwards, so it's an error for `time` to be earlier than the current
+    // time, or not finite.
+    pub fn derive_to(&mut self, time: f64) -> Result<bool, LSystemError> {
+        if !(time >= self.time && time.is_finite()) {
+            return Err(LSystemError::invalid_time(time, self.time));
+        }
+        self.time = time;
+        let mut changed = false;
+        loop {
+            let mut rewritten = false;
+            self.next_string.clear();
+            for (index, module) in self.string.iter().enumerate() {
+                let expiry = module.birth + module.lifetime;
+                if expiry <= time && self.productions.rewrite_module(module, expiry, &mut self.random, &mut self.next_string)
+                    .map_err(|error| error.at_step(self.steps + 1).at_module(index))? {
+                    rewritten = true;
+                    continue;
                 }
+                self.next_string.push_module(module);
             }
-            if !found_production {
-                self.string.0.push(module);
+            if !rewritten {
+                return Ok(changed);
             }
+            changed = true;
+            self.steps += 1;
+            std::mem::swap(&mut self.string, &mut self.next_string);
+            self.decompose()?;
         }
     }
-    pub fn step_by(&mut self, iterations: usize) {
-        for _ in 0..iterations {
-            self.step();
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+    // The earliest time a module with productions reaches its lifetime. derive_to any earlier time leaves the string as it is.
+    pub fn next_expiry(&self) -> f64 {
+        self.string.iter()
+            .filter(|module| self.productions.0.contains_key(&module.symbol))
+            .map(|module| module.birth + module.lifetime)
+            .fold(f64::INFINITY, f64::min)
+    }
+    // Whether any module is younger than its lifetime, so make_mesh draws it partly grown and the mesh changes as time passes.
+    pub fn is_growing(&self) -> bool {
+        self.string.iter().any(|module| module.birth + module.lifetime > self.time)
+    }
+
+    // Interprets the current string with the turtle, and fills in the parameters of its query modules from the turtle's state at each one.
+    // Call this before step or derive_to so productions see up to date values. Query modules created by interpretation rules aren't answered.
+    pub fn query(&mut self, environment: &mut dyn Environment) -> Result<(), LSystemError> {
+        let growth = Growth { time: self.time };
+        let mut interpreter = TurtleInterpreter::new(&self.system.turtle, growth, &self.surfaces);
+        let mut expansion = LString::default();
+        let mut random = Random(self.seed);
+        for index in 0..self.string.len() {
+            let result = if let Some(kind) = query_kind(self.string.symbols[index]) {
+                interpreter.answer_query(kind, self.string.params_mut(index), environment);
+                Ok(())
+            } else if self.interpretations.is_empty() {
+                interpreter.interpret(self.string.get(index))
+            } else {
+                expansion.clear();
+                self.interpretations.rewrite_recursive(self.string.get(index), 0, &mut random, &mut expansion)
+                    .and_then(|_| expansion.iter().try_for_each(|module| interpreter.interpret(module)))
+            };
+            result.map_err(|error| error.at_step(self.steps).at_module(index))?;
         }
+        Ok(())
     }
 
     pub fn current_string(&self) -> &LString {
         &self.string
     }
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    // Applies the interpretation rules to the current string. These only affect geometry, and never feed back into derivation.
+    // Stochastic interpretation rules are reseeded each time, so the same string is always interpreted the same way.
+    pub fn interpreted_string(&self) -> Result<LString, LSystemError> {
+        let mut output = LString::default();
+        self.interpretations.rewrite_string(&self.string, &mut Random(self.seed), &mut output).map_err(|error| error.at_step(self.steps))?;
+        Ok(output)
+    }
+
+    // Errors from turtle commands give the index of the module in the interpreted string.
+    pub fn make_mesh(&self) -> Result<TriangleMesh, LSystemError> {
+        let growth = Growth { time: self.time };
+        let mesh = if self.interpretations.is_empty() {
+            TurtleInterpreter::make_mesh(&self.string, &self.system.turtle, growth, &self.surfaces)
+        } else {
+            TurtleInterpreter::make_mesh(&self.interpreted_string()?, &self.system.turtle, growth, &self.surfaces)
+        };
+        mesh.map_err(|error| error.at_step(self.steps))
+    }
+
+    // Writes the current mesh in the format matching the path's extension, as GenerateMesh::export_mesh does.
+    pub fn export_mesh<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(crate::mesh_io::save_mesh(&self.make_mesh()?, path)?)
+    }
 }
 
 pub fn test_mesh() -> TriangleMesh {
     let lsystem_source = include_str!("../input/system.txt");
     let mut lsystem = match crate::syntax::parse_string(system(), lsystem_source) {
-        Ok(system) => LSystem::new(system),
+        Ok(system) => LSystem::new(system).unwrap_or_else(|error| panic!("{}", error)),
         Err(error) => {
             println!("{}", error);
             panic!("Failed to parse LSystem")
         }
     };  
-    lsystem.start();
-    lsystem.step_by(20);
-    TurtleInterpreter::make_mesh(lsystem.current_string())
+    lsystem.start().and_then(|_| lsystem.step_by(20)).and_then(|_| lsystem.make_mesh()).unwrap_or_else(|error| panic!("{}", error))
+}
+
+#[derive(Clone, Copy)]
+struct Turtle {
+    transform: Transform,
+    width: f32,
+    color: usize,
 }
 
-struct TurtleInterpreter {
-    turtle: Transform,
-    stack: Vec<Transform>,
-    current_polygon: Option<Vec<Point3f>>,
-    last_polygon_normal: Vector3f,
+struct Polygon {
+    up: Vector3f,
+    vertices: Vec<Point3f>,
+}
+
+// Scales the lengths and angles of modules by how far through their lifetime they are.
+#[derive(Clone, Copy)]
+struct Growth {
+    time: f64,
+}
+
+impl Growth {
+    fn fraction(&self, module: LSymbol) -> f64 {
+        ((self.time - module.birth) / module.lifetime).clamp(0.0, 1.0)
+    }
+}
+
+struct TurtleInterpreter<'a> {
+    settings: &'a TurtleSettings,
+    growth: Growth,
+    turtle: Turtle,
+    stack: Vec<Turtle>,
+    current_polygon: Option<Polygon>,
+    surfaces: &'a [Option<TriangleMesh>],
     mesh: TriangleMesh,
 }
 
-impl TurtleInterpreter {
-    fn make_mesh(string: &LString) -> TriangleMesh {
-        let mut interpreter = TurtleInterpreter {
-            turtle: Transform::from_rotation(Quaternion::look_at(Vector3f::unit_y(), -Vector3f::unit_z())),
+impl<'a> TurtleInterpreter<'a> {
+    fn new(settings: &'a TurtleSettings, growth: Growth, surfaces: &'a [Option<TriangleMesh>]) -> TurtleInterpreter<'a> {
+        TurtleInterpreter {
+            settings,
+            growth,
+            turtle: Turtle {
+                transform: Transform::from_rotation(Quaternion::look_at(settings.heading, settings.up)),
+                width: settings.width as f32,
+                color: 0,
+            },
             stack: Vec::new(),
             current_polygon: None,
-            last_polygon_normal: Vector3f::zero(),
+            surfaces,
             mesh: TriangleMesh::default(),
+        }
+    }
+
+    fn make_mesh(string: &LString, settings: &'a TurtleSettings, growth: Growth, surfaces: &'a [Option<TriangleMesh>]) -> Result<TriangleMesh, LSystemError> {
+        let mut interpreter = TurtleInterpreter::new(settings, growth, surfaces);
+        for (index, module) in string.iter().enumerate() {
+            interpreter.interpret(module).map_err(|error| error.at_module(index))?;
+        }
+        Ok(interpreter.mesh)
+    }
+
+    // Surfaces are modeled in the turtle's own frame and fully grown. Their colors are dropped, so they take the color current where they're drawn.
+    fn make_surface(string: &LString, settings: &'a TurtleSettings) -> Result<TriangleMesh, LSystemError> {
+        let mut interpreter = TurtleInterpreter::new(settings, Growth { time: f64::INFINITY }, &[]);
+        interpreter.turtle.transform = Transform::identity();
+        for (index, module) in string.iter().enumerate() {
+            interpreter.interpret(module).map_err(|error| error.at_module(index))?;
+        }
+        interpreter.mesh.colors.clear();
+        Ok(interpreter.mesh)
+    }
+
+    fn interpret(&mut self, module: LSymbol) -> Result<(), LSystemError> {
+        let symbol = match module.symbol.as_char() {
+            Some(symbol) => symbol,
+            None => return Ok(()),
         };
-        for module in string.iter() {
-            match module.symbol {
-                'F' => unimplemented!(),
-                'f' => unimplemented!(),
-                '+' => interpreter.rotate_turtle(Vector3f::unit_y(), Deg(module.params[0] as f32)),
-                '-' => interpreter.rotate_turtle(-Vector3f::unit_y(), Deg(module.params[0] as f32)),
-                '&' => interpreter.rotate_turtle(Vector3f::unit_x(), Deg(module.params[0] as f32)),
-                '/' => interpreter.rotate_turtle(Vector3f::unit_z(), Deg(module.params[0] as f32)),
-                '|' => interpreter.rotate_turtle(Vector3f::unit_y(), Deg(180.0)),
-                '[' => interpreter.stack.push(interpreter.turtle),
-                ']' => interpreter.turtle = interpreter.stack.pop().expect("mismatched ']'"),
-                '{' => interpreter.start_polygon(),
-                '}' => interpreter.end_polygon(),
-                '.' => interpreter.add_polygon_vertex(),
-                'G' => interpreter.move_turtle(module.params[0] as f32),
-                _ => (),
+        match symbol {
+            'F' => self.draw_segment(self.distance(module)),
+            'f' => self.move_turtle(self.distance(module)),
+            '+' => self.rotate_turtle(Vector3f::unit_y(), self.angle(module)),
+            '-' => self.rotate_turtle(-Vector3f::unit_y(), self.angle(module)),
+            '&' => self.rotate_turtle(Vector3f::unit_x(), self.angle(module)),
+            '^' => self.rotate_turtle(-Vector3f::unit_x(), self.angle(module)),
+            '/' => self.rotate_turtle(Vector3f::unit_z(), self.angle(module)),
+            '\\' => self.rotate_turtle(-Vector3f::unit_z(), self.angle(module)),
+            '|' => self.rotate_turtle(Vector3f::unit_y(), Deg(180.0)),
+            '$' => self.level_turtle(),
+            '!' => self.turtle.width = module.param_or(0, self.settings.width) as f32,
+            '\'' => self.turtle.color = module.param_or(0, (self.turtle.color + 1) as f64) as usize,
+            '[' => self.stack.push(self.turtle),
+            ']' => self.turtle = self.stack.pop().ok_or_else(|| LSystemError::unmatched(']'))?,
+            '{' => self.start_polygon()?,
+            '}' => self.end_polygon()?,
+            '.' => self.add_polygon_vertex()?,
+            'G' => self.move_turtle(self.distance(module)),
+            '~' => self.add_surface(module),
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn answer_query(&mut self, kind: char, params: &mut [f64], environment: &mut dyn Environment) {
+        let transform = &self.turtle.transform;
+        let vector = match kind {
+            'P' => transform.disp,
+            'H' => transform.rot * Vector3f::unit_z(),
+            'U' => transform.rot * Vector3f::unit_y(),
+            'L' => transform.rot * Vector3f::unit_x(),
+            'E' => {
+                environment.query(Point3f::from_vec(transform.disp), transform.rot * Vector3f::unit_z(), params);
+                return;
             }
+            _ => return,
+        };
+        for (param, value) in params.iter_mut().zip([vector.x, vector.y, vector.z]) {
+            *param = value as f64;
         }
-        interpreter.mesh
+    }
+
+    fn angle(&self, module: LSymbol) -> Deg<f32> {
+        Deg((module.param_or(0, self.settings.angle) * self.growth.fraction(module)) as f32)
+    }
+    fn distance(&self, module: LSymbol) -> f32 {
+        (module.param_or(0, self.settings.step) * self.growth.fraction(module)) as f32
+    }
+
+    fn color(&self) -> [f32; 4] {
+        self.settings.palette.get(self.turtle.color).copied().unwrap_or_else(Color::white).into()
     }
 
     fn move_turtle(&mut self, distance: f32) {
-        self.turtle.disp += self.turtle.rot * Vector3f::new(0.0, 0.0, distance);
+        let transform = &mut self.turtle.transform;
+        transform.disp += transform.rot * Vector3f::new(0.0, 0.0, distance);
     }
     fn rotate_turtle(&mut self, axis: Vector3f, angle: Deg<f32>) {
-        self.turtle.rot = self.turtle.rot * Quaternion::from_axis_angle(axis, angle);
+        let transform = &mut self.turtle.transform;
+        transform.rot = transform.rot * Quaternion::from_axis_angle(axis, angle);
+    }
+    // Rolls the turtle around its heading so that its left vector is horizontal, perpendicular to the world's up vector (against
+    // gravity), whatever the system's #heading is.
+    fn level_turtle(&mut self) {
+        let transform = &mut self.turtle.transform;
+        let heading = transform.rot * Vector3f::unit_z();
+        let left = Vector3f::unit_y().cross(heading);
+        if left.magnitude2() < f32::EPSILON {
+            return;
+        }
+        let left = left.normalize();
+        let up = heading.cross(left);
+        transform.rot = Quaternion::from(Matrix3::from_cols(left, up, heading));
+    }
+    // Bends the turtle's heading towards the tropism vector, proportionally to how far it points away from it.
+    fn apply_tropism(&mut self) {
+        if self.settings.susceptibility == 0.0 {
+            return;
+        }
+        let transform = &mut self.turtle.transform;
+        let heading = transform.rot * Vector3f::unit_z();
+        let axis = heading.cross(self.settings.tropism);
+        let magnitude = axis.magnitude();
+        if magnitude < f32::EPSILON {
+            return;
+        }
+        let angle = Rad(self.settings.susceptibility as f32 * magnitude);
+        transform.rot = Quaternion::from_axis_angle(axis / magnitude, angle) * transform.rot;
     }
 
-    fn start_polygon(&mut self) {
-        assert!(self.current_polygon.is_none(), "mismatched '{{'");
-        self.current_polygon = Some(Vec::new());
+    fn draw_segment(&mut self, distance: f32) {
+        let start = self.turtle.transform;
+        self.move_turtle(distance);
+        if self.current_polygon.is_none() {
+            self.add_segment(start, self.turtle.transform.disp);
+        }
+        self.apply_tropism();
     }
-    fn end_polygon(&mut self) {
-        assert!(self.current_polygon.is_some(), "mismatched '}}'");
-        let mut polygon = self.current_polygon.take().unwrap();
-        polygon.dedup();
-        if polygon.len() >= 3 {
-            self.add_triangle_fan(polygon);
+    // Adds a square prism from `start` to `end`, oriented to the turtle's left and up vectors at `start`. Its UVs wrap u once
+    // around it, and run v along it at the same scale, so textures aren't stretched however long the segment is.
+    fn add_segment(&mut self, start: Transform, end: Vector3f) {
+        let left = start.rot * Vector3f::unit_x();
+        let up = start.rot * Vector3f::unit_y();
+        let radius = self.turtle.width / 2.0;
+        let corners = [(left + up) * radius, (up - left) * radius, (-left - up) * radius, (left - up) * radius];
+        // Each side is as wide as the segment, so four of them go around it.
+        let length = if radius > 0.0 { (end - start.disp).magnitude() / (4.0 * self.turtle.width) } else { 0.0 };
+        for i in 0..corners.len() {
+            let a = corners[i];
+            let b = corners[(i + 1) % corners.len()];
+            let normal: [f32; 3] = (a + b).normalize().into();
+            let (u0, u1) = (i as f32 / corners.len() as f32, (i + 1) as f32 / corners.len() as f32);
+            let base = self.mesh.positions.len() as u32;
+            self.mesh.positions.extend([start.disp + a, start.disp + b, end + b, end + a].iter().map(|v| Into::<[f32; 3]>::into(*v)));
+            self.mesh.normals.extend(std::iter::repeat(normal).take(4));
+            self.mesh.colors.extend(std::iter::repeat(self.color()).take(4));
+            self.mesh.uvs.extend([[u0, 0.0], [u1, 0.0], [u1, length], [u0, length]]);
+            self.mesh.indices.extend([base, base + 1, base + 2, base, base + 2, base + 3]);
         }
     }
-    fn add_polygon_vertex(&mut self) {
-        assert!(self.current_polygon.is_some(), "'.' outside of {{ }}");
-        self.current_polygon.as_mut().unwrap().push(Point3f::from_vec(self.turtle.disp));
+
+    // Copies a surface into the mesh, scaled and growing like a segment, with its axes along the turtle's left, up and heading vectors.
+    fn add_surface(&mut self, module: LSymbol) {
+        let surface = match self.surfaces.get(module.param_or(0, 0.0) as usize) {
+            Some(Some(surface)) => surface,
+            _ => return,
+        };
+        let transform = Transform {
+            scale: (module.param_or(1, 1.0) * self.growth.fraction(module)) as f32,
+            ..self.turtle.transform
+        };
+        let base = self.mesh.positions.len() as u32;
+        self.mesh.positions.extend(surface.positions.iter().map(|p| Into::<[f32; 3]>::into(transform.transform_point(Point3f::from(*p)))));
+        self.mesh.normals.extend(surface.normals.iter().map(|n| Into::<[f32; 3]>::into(transform.rot * Vector3f::from(*n))));
+        if surface.colors.is_empty() {
+            self.mesh.colors.extend(std::iter::repeat(self.color()).take(surface.positions.len()));
+        } else {
+            self.mesh.colors.extend(surface.colors.iter().copied());
+        }
+        // Surfaces without UVs have no texture to show, so any will do.
+        if surface.uvs.is_empty() {
+            self.mesh.uvs.extend(std::iter::repeat([0.0; 2]).take(surface.positions.len()));
+        } else {
+            self.mesh.uvs.extend(surface.uvs.iter().copied());
+        }
+        self.mesh.indices.extend(surface.indices.iter().map(|index| base + index));
+        // The rest of the turtle's geometry has no tangents, so neither does its mesh. load_mesh generates them from the UVs.
+        self.mesh.tangents.clear();
     }
 
-    fn surface_normal(vertices: &[Point3f]) -> Vector3f {
-        let mut normal = Vector3f::zero();
-        for i in 0..vertices.len() {
-            let current = vertices[i];
-            let next = vertices[(i + 1) % vertices.len()];
-            normal.x += (current.y - next.y) * (current.z + next.z);
-            normal.y += (current.z - next.z) * (current.x + next.x);
-            normal.z += (current.x - next.x) * (current.y + next.y);
+    fn start_polygon(&mut self) -> Result<(), LSystemError> {
+        if self.current_polygon.is_some() {
+            return Err(LSystemError::nested_polygon());
         }
-        normal.normalize()
+        self.current_polygon = Some(Polygon {
+            up: self.turtle.transform.rot * Vector3f::unit_y(),
+            vertices: Vec::new(),
+        });
+        Ok(())
     }
-    fn add_triangle_fan(&mut self, vertices: Vec<Point3f>) {
-        fn triangulate_face(indices: &mut Vec<u32>, face_indices: std::ops::Range<usize>) {
-            let start = face_indices.start as u32;
-            for i in 2..(face_indices.len() as u32) {
-                indices.push(start + 0);
-                indices.push(start + i - 1);
-                indices.push(start + i);
-            }
+    fn end_polygon(&mut self) -> Result<(), LSystemError> {
+        let mut polygon = self.current_polygon.take().ok_or_else(|| LSystemError::unmatched('}'))?;
+        polygon.vertices.dedup();
+        if polygon.vertices.len() > 1 && polygon.vertices.first() == polygon.vertices.last() {
+            polygon.vertices.pop();
+        }
+        if polygon.vertices.len() >= 3 {
+            self.add_polygon(polygon);
         }
+        Ok(())
+    }
+    fn add_polygon_vertex(&mut self) -> Result<(), LSystemError> {
+        let polygon = self.current_polygon.as_mut().ok_or_else(LSystemError::vertex_outside_polygon)?;
+        polygon.vertices.push(Point3f::from_vec(self.turtle.transform.disp));
+        Ok(())
+    }
 
-        let mut normal = TurtleInterpreter::surface_normal(&vertices);
-        // Ensure all normals are roughly in the same direction, so faces with opposite winding can have correct normals.
-        // TODO would be better to enforce consistent winding.
-        if normal.dot(self.last_polygon_normal) < 0.0 {
+    // The front face of a polygon is the side the turtle's up vector pointed to when the polygon was started.
+    fn add_polygon(&mut self, mut polygon: Polygon) {
+        let normal = polygon_normal(&polygon.vertices);
+        if normal.magnitude2() < f32::EPSILON {
+            return;
+        }
+        let mut normal = normal.normalize();
+        if normal.dot(polygon.up) < 0.0 {
+            polygon.vertices.reverse();
             normal = -normal;
         }
-        self.last_polygon_normal = normal;
-
-        let start_index = self.mesh.positions.len();
+        let triangles = triangulate_polygon(&polygon.vertices, normal);
+        self.add_face(&polygon.vertices, normal, &triangles, false);
+        if self.settings.double_sided {
+            self.add_face(&polygon.vertices, -normal, &triangles, true);
+        }
+    }
+    // UVs are the vertices' coordinates in the polygon's plane, with u along its first edge, in world units.
+    fn add_face(&mut self, vertices: &[Point3f], normal: Vector3f, triangles: &[u32], flip: bool) {
+        let start_index = self.mesh.positions.len() as u32;
+        let u_axis = (vertices[1] - vertices[0]).normalize();
+        let v_axis = normal.cross(u_axis);
         self.mesh.positions.extend(vertices.iter().map(|v| Into::<[f32; 3]>::into(*v)));
         self.mesh.normals.extend(std::iter::repeat(Into::<[f32; 3]>::into(normal)).take(vertices.len()));
-        triangulate_face(&mut self.mesh.indices, start_index..self.mesh.positions.len());
+        self.mesh.colors.extend(std::iter::repeat(self.color()).take(vertices.len()));
+        self.mesh.uvs.extend(vertices.iter().map(|v| [(v - vertices[0]).dot(u_axis), (v - vertices[0]).dot(v_axis)]));
+        for triangle in triangles.chunks(3) {
+            if flip {
+                self.mesh.indices.extend([start_index + triangle[0], start_index + triangle[2], start_index + triangle[1]]);
+            } else {
+                self.mesh.indices.extend(triangle.iter().map(|index| start_index + index));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::parse_string;
+
+    fn started(text: &str) -> LSystem {
+        let mut lsystem = LSystem::new(parse_string(system(), text).unwrap_or_else(|error| panic!("{}", error))).unwrap();
+        lsystem.start().unwrap();
+        lsystem
+    }
+
+    #[test]
+    fn derive_to_rejects_earlier_and_non_finite_times() {
+        let mut lsystem = started("#axiom A\nA => A B");
+        assert!(lsystem.derive_to(2.5).unwrap());
+        for time in [1.0, f64::NAN, f64::INFINITY] {
+            assert!(lsystem.derive_to(time).is_err(), "derived to {}", time);
+        }
+        assert_eq!((lsystem.time(), lsystem.current_string().len()), (2.5, 3));
+    }
+
+    #[test]
+    fn evaluated_lifetimes_are_at_least_the_minimum() {
+        let mut lsystem = started("#axiom A(0)\nA(t) => A(t)@(t*0)");
+        lsystem.derive_to(1.0).unwrap();
+        assert_eq!(lsystem.current_string().get(0).lifetime, DEFAULT_LIFETIME);
+        let mut lsystem = started("#axiom A(0.000001)\nA(t) => A(t)@t");
+        lsystem.derive_to(1.0).unwrap();
+        assert_eq!(lsystem.current_string().get(0).lifetime, MIN_LIFETIME);
     }
 }
@@ -25,20 +25,10 @@
 // expensive otherwise. It has some drawbacks, which are the fact that transparent objects must be
 // drawn after the lighting, and that the whole process consumes more memory.
 
-mod color;
-mod code;
-mod frame;
-mod mesh_generation;
-mod syntax;
-mod lsystem;
-mod triangle_draw;
-mod transform;
-mod world;
-
-use crate::color::Color;
-use crate::frame::*;
-use crate::triangle_draw::*;
-use crate::world::World;
+use realm::color::Color;
+use realm::frame::*;
+use realm::triangle_draw::*;
+use realm::world::World;
 use cgmath::Point3;
 use cgmath::Vector3;
 use vulkano::device::physical::{PhysicalDevice, PhysicalDeviceType};
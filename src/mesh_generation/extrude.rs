@@ -1,5 +1,104 @@
-use crate::{triangle_draw::TriangleMesh, transform::Vector3f};
+use std::collections::{HashMap, HashSet};
 
-pub fn extrude_mesh(mesh: &mut TriangleMesh, extrude: Vector3f) {
-    //unimplemented!();
+use cgmath::InnerSpace;
+
+use crate::{triangle_draw::TriangleMesh, transform::{Point3f, Vector3f}};
+
+use super::utilities::{generate_tangents, position_key};
+
+// An edge used by only one triangle, in that triangle's winding order. `opposite` is the triangle's third vertex.
+struct BoundaryEdge {
+    start: u32,
+    end: u32,
+    opposite: u32,
+}
+
+// Vertices are matched by position, so meshes with split normals or colors still have their boundary found correctly.
+fn boundary_edges(mesh: &TriangleMesh) -> Vec<BoundaryEdge> {
+    let mut canonical = HashMap::new();
+    let keys: Vec<u32> = mesh.positions.iter().enumerate()
+        .map(|(index, position)| *canonical.entry(position_key(*position)).or_insert(index as u32))
+        .collect();
+    let mut edges = HashSet::new();
+    for triangle in mesh.indices.chunks_exact(3) {
+        for i in 0..3 {
+            edges.insert((keys[triangle[i] as usize], keys[triangle[(i + 1) % 3] as usize]));
+        }
+    }
+    let mut boundary = Vec::new();
+    for triangle in mesh.indices.chunks_exact(3) {
+        for i in 0..3 {
+            let (start, end, opposite) = (triangle[i], triangle[(i + 1) % 3], triangle[(i + 2) % 3]);
+            let (start_key, end_key) = (keys[start as usize], keys[end as usize]);
+            if start_key != end_key && !edges.contains(&(end_key, start_key)) {
+                boundary.push(BoundaryEdge { start, end, opposite });
+            }
+        }
+    }
+    boundary
+}
+
+// Extrudes the boundary edges of a mesh by `extrude` into side walls, so an open surface looks like a solid of that thickness.
+// Each wall faces away from the triangle its edge belongs to. If `cap` is set, a copy of the surface facing the other way
+// closes the far end. Holes in the mesh get inward facing walls too. Closed meshes have no boundary, and are left unchanged.
+// Walls copy the UVs of the edges they're extruded from, so their texture is stretched along `extrude`; remap them if that matters.
+pub fn extrude_mesh(mesh: &mut TriangleMesh, extrude: Vector3f, cap: bool) {
+    let boundary = boundary_edges(mesh);
+    let vertex_count = mesh.positions.len();
+    let index_count = mesh.indices.len();
+    let has_colors = !mesh.colors.is_empty();
+    let has_uvs = !mesh.uvs.is_empty();
+    let has_tangents = !mesh.tangents.is_empty();
+
+    for edge in boundary.iter() {
+        let start = Point3f::from(mesh.positions[edge.start as usize]);
+        let end = Point3f::from(mesh.positions[edge.end as usize]);
+        let opposite = Point3f::from(mesh.positions[edge.opposite as usize]);
+        let mut normal = extrude.cross(end - start);
+        if normal.magnitude2() < f32::EPSILON {
+            continue;
+        }
+        normal = normal.normalize();
+        let mut corners = [start, start + extrude, end + extrude, end];
+        let mut sources = [edge.start, edge.start, edge.end, edge.end];
+        let midpoint = start + (end - start) / 2.0;
+        if normal.dot(midpoint - opposite) < 0.0 {
+            normal = -normal;
+            corners.reverse();
+            sources.reverse();
+        }
+        let base = mesh.positions.len() as u32;
+        mesh.positions.extend(corners.iter().map(|corner| Into::<[f32; 3]>::into(*corner)));
+        mesh.normals.extend(std::iter::repeat(Into::<[f32; 3]>::into(normal)).take(4));
+        if has_colors {
+            mesh.colors.extend(sources.iter().map(|source| mesh.colors[*source as usize]).collect::<Vec<_>>());
+        }
+        if has_uvs {
+            mesh.uvs.extend(sources.iter().map(|source| mesh.uvs[*source as usize]).collect::<Vec<_>>());
+        }
+        mesh.indices.extend([base, base + 1, base + 2, base, base + 2, base + 3]);
+    }
+
+    if cap {
+        let base = mesh.positions.len() as u32;
+        for index in 0..vertex_count {
+            let position = Point3f::from(mesh.positions[index]) + extrude;
+            let normal = -Vector3f::from(mesh.normals[index]);
+            mesh.positions.push(position.into());
+            mesh.normals.push(normal.into());
+            if has_colors {
+                mesh.colors.push(mesh.colors[index]);
+            }
+            if has_uvs {
+                mesh.uvs.push(mesh.uvs[index]);
+            }
+        }
+        for i in (0..index_count).step_by(3) {
+            let triangle = [mesh.indices[i], mesh.indices[i + 1], mesh.indices[i + 2]];
+            mesh.indices.extend([base + triangle[0], base + triangle[2], base + triangle[1]]);
+        }
+    }
+    if has_tangents {
+        generate_tangents(mesh);
+    }
 }
@@ -1,9 +1,23 @@
+pub mod primitives;
+
 mod extrude;
+mod simplify;
+mod surface_nets;
+mod triangulate;
+mod utilities;
 
 use crate::triangle_draw::TriangleMesh;
 
 pub use extrude::extrude_mesh;
+pub use simplify::{simplify_mesh, lod_chain};
+pub use surface_nets::surface_nets;
+pub use triangulate::{polygon_normal, triangulate_polygon};
+pub use utilities::{MeshError, validate_mesh, weld_vertices, remove_degenerate_triangles, smooth_normals, generate_tangents};
 
 pub trait GenerateMesh {
     fn generate_mesh(&self) -> TriangleMesh;
+
+    fn export_mesh<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+        crate::mesh_io::save_mesh(&self.generate_mesh(), path)
+    }
 }
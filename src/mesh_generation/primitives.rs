@@ -0,0 +1,175 @@
+use std::{collections::HashMap, f32::consts::PI};
+
+use cgmath::InnerSpace;
+
+use crate::{triangle_draw::TriangleMesh, transform::Vector3f};
+
+use super::utilities::remove_degenerate_triangles;
+
+// Primitives are centered on the origin with +y up, and sized to fit the standard cube, which spans -1 to 1 on each axis.
+// Their front faces wind counter-clockwise, facing outwards.
+
+// Adds a grid of (columns + 1) by (rows + 1) vertices. `vertex(column, row)` gives each one's position and normal, and must be
+// parameterized so that the column direction crossed with the row direction points along the normal. UVs run from 0 to 1
+// across the grid, with u along the columns and v along the rows.
+fn add_grid(mesh: &mut TriangleMesh, columns: usize, rows: usize, vertex: impl Fn(usize, usize) -> (Vector3f, Vector3f)) {
+    let base = mesh.positions.len() as u32;
+    for row in 0..=rows {
+        for column in 0..=columns {
+            let (position, normal) = vertex(column, row);
+            mesh.positions.push(position.into());
+            mesh.normals.push(normal.normalize().into());
+            mesh.uvs.push([column as f32 / columns as f32, row as f32 / rows as f32]);
+        }
+    }
+    let stride = columns as u32 + 1;
+    for row in 0..rows as u32 {
+        for column in 0..columns as u32 {
+            let a = base + row * stride + column;
+            let (b, c, d) = (a + 1, a + stride + 1, a + stride);
+            mesh.indices.extend([a, b, c, a, c, d]);
+        }
+    }
+}
+
+// A flat disc at height `y`, facing up or down, with UVs mapping it onto the unit square.
+fn add_disc(mesh: &mut TriangleMesh, y: f32, radius: f32, segments: usize, up: bool) {
+    let normal = if up { Vector3f::unit_y() } else { -Vector3f::unit_y() };
+    let center = mesh.positions.len() as u32;
+    mesh.positions.push([0.0, y, 0.0]);
+    mesh.normals.push(normal.into());
+    mesh.uvs.push([0.5, 0.5]);
+    for segment in 0..=segments {
+        let angle = 2.0 * PI * segment as f32 / segments as f32;
+        mesh.positions.push([radius * angle.cos(), y, -radius * angle.sin()]);
+        mesh.normals.push(normal.into());
+        mesh.uvs.push([0.5 + 0.5 * angle.cos(), 0.5 + 0.5 * angle.sin()]);
+    }
+    for segment in 0..segments as u32 {
+        let (a, b) = (center + 1 + segment, center + 2 + segment);
+        mesh.indices.extend(if up { [center, a, b] } else { [center, b, a] });
+    }
+}
+
+// Point on a sphere of radius 1 at `longitude` around the y axis and `latitude` up from the south pole, both in radians.
+fn sphere_point(longitude: f32, latitude: f32) -> Vector3f {
+    let ring = latitude.sin();
+    Vector3f::new(ring * longitude.cos(), -latitude.cos(), -ring * longitude.sin())
+}
+
+pub fn uv_sphere(segments: usize, rings: usize) -> TriangleMesh {
+    let (segments, rings) = (segments.max(3), rings.max(2));
+    let mut mesh = TriangleMesh::default();
+    add_grid(&mut mesh, segments, rings, |column, row| {
+        let point = sphere_point(2.0 * PI * column as f32 / segments as f32, PI * row as f32 / rings as f32);
+        (point, point)
+    });
+    // The triangles touching the poles have two corners there.
+    remove_degenerate_triangles(&mut mesh);
+    mesh
+}
+
+// An icosahedron with each face split into four `subdivisions` times, projected onto the sphere. It has no UVs, since mapping a
+// texture onto it needs a seam of split vertices; use uv_sphere for textured spheres.
+pub fn ico_sphere(subdivisions: usize) -> TriangleMesh {
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+    let mut positions: Vec<Vector3f> = [
+        [-1.0, t, 0.0], [1.0, t, 0.0], [-1.0, -t, 0.0], [1.0, -t, 0.0],
+        [0.0, -1.0, t], [0.0, 1.0, t], [0.0, -1.0, -t], [0.0, 1.0, -t],
+        [t, 0.0, -1.0], [t, 0.0, 1.0], [-t, 0.0, -1.0], [-t, 0.0, 1.0],
+    ].iter().map(|p| Vector3f::from(*p).normalize()).collect();
+    let mut faces: Vec<[u32; 3]> = vec![
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+    for _ in 0..subdivisions {
+        let mut midpoints = HashMap::new();
+        let mut midpoint = |a: u32, b: u32| *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+            positions.push((positions[a as usize] + positions[b as usize]).normalize());
+            positions.len() as u32 - 1
+        });
+        faces = faces.iter().flat_map(|&[a, b, c]| {
+            let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+            [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+        }).collect();
+    }
+    TriangleMesh {
+        positions: positions.iter().map(|p| (*p).into()).collect(),
+        normals: positions.iter().map(|p| (*p).into()).collect(),
+        indices: faces.concat(),
+        ..TriangleMesh::default()
+    }
+}
+
+pub fn cylinder(segments: usize) -> TriangleMesh {
+    let segments = segments.max(3);
+    let mut mesh = TriangleMesh::default();
+    add_grid(&mut mesh, segments, 1, |column, row| {
+        let angle = 2.0 * PI * column as f32 / segments as f32;
+        let normal = Vector3f::new(angle.cos(), 0.0, -angle.sin());
+        (normal + Vector3f::new(0.0, row as f32 * 2.0 - 1.0, 0.0), normal)
+    });
+    add_disc(&mut mesh, -1.0, 1.0, segments, false);
+    add_disc(&mut mesh, 1.0, 1.0, segments, true);
+    mesh
+}
+
+// Points up, with its apex at y = 1.
+pub fn cone(segments: usize) -> TriangleMesh {
+    let segments = segments.max(3);
+    let mut mesh = TriangleMesh::default();
+    add_grid(&mut mesh, segments, 1, |column, row| {
+        let angle = 2.0 * PI * column as f32 / segments as f32;
+        let out = Vector3f::new(angle.cos(), 0.0, -angle.sin());
+        // The side rises 2 for every 1 it moves in, so its normal leans up by half.
+        let normal = out + Vector3f::new(0.0, 0.5, 0.0);
+        (out * (1 - row) as f32 + Vector3f::new(0.0, row as f32 * 2.0 - 1.0, 0.0), normal)
+    });
+    // The apex is one vertex per segment, so each side keeps its own normal, but the triangles that meet there have no area.
+    remove_degenerate_triangles(&mut mesh);
+    add_disc(&mut mesh, -1.0, 1.0, segments, false);
+    mesh
+}
+
+// A cylinder of the given radius with hemispherical ends, 2 units tall overall. `rings` is per hemisphere.
+pub fn capsule(radius: f32, segments: usize, rings: usize) -> TriangleMesh {
+    let (segments, rings) = (segments.max(3), rings.max(1));
+    let radius = radius.clamp(0.0, 1.0);
+    let mut mesh = TriangleMesh::default();
+    // Rows 0 to `rings` are the bottom hemisphere, and the rest the top one. The two rows at the equator are joined by the cylinder.
+    add_grid(&mut mesh, segments, 2 * rings + 1, |column, row| {
+        let (offset, ring) = if row <= rings { (radius - 1.0, row) } else { (1.0 - radius, row - 1) };
+        let normal = sphere_point(2.0 * PI * column as f32 / segments as f32, PI / 2.0 * ring as f32 / rings as f32);
+        (normal * radius + Vector3f::new(0.0, offset, 0.0), normal)
+    });
+    remove_degenerate_triangles(&mut mesh);
+    mesh
+}
+
+// Lies flat in the xz plane. `radius` is the radius of the tube, and the ring running through its middle has radius 1 - `radius`.
+pub fn torus(radius: f32, segments: usize, tube_segments: usize) -> TriangleMesh {
+    let (segments, tube_segments) = (segments.max(3), tube_segments.max(3));
+    let radius = radius.clamp(0.0, 0.5);
+    let mut mesh = TriangleMesh::default();
+    add_grid(&mut mesh, segments, tube_segments, |column, row| {
+        let angle = 2.0 * PI * column as f32 / segments as f32;
+        let tube_angle = 2.0 * PI * row as f32 / tube_segments as f32;
+        let out = Vector3f::new(angle.cos(), 0.0, -angle.sin());
+        let normal = out * tube_angle.cos() + Vector3f::new(0.0, tube_angle.sin(), 0.0);
+        (out * (1.0 - radius) + normal * radius, normal)
+    });
+    mesh
+}
+
+// A square in the xz plane facing up, split into `subdivisions` by `subdivisions` quads.
+pub fn plane(subdivisions: usize) -> TriangleMesh {
+    let subdivisions = subdivisions.max(1);
+    let mut mesh = TriangleMesh::default();
+    add_grid(&mut mesh, subdivisions, subdivisions, |column, row| {
+        let (u, v) = (column as f32 / subdivisions as f32, row as f32 / subdivisions as f32);
+        (Vector3f::new(2.0 * u - 1.0, 0.0, 1.0 - 2.0 * v), Vector3f::unit_y())
+    });
+    mesh
+}
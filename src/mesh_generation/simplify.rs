@@ -0,0 +1,317 @@
+use std::{cmp::Ordering, collections::{BinaryHeap, HashMap}};
+
+use cgmath::{Deg, InnerSpace, Point3, Vector3};
+
+use crate::triangle_draw::TriangleMesh;
+
+use super::utilities::{position_key, smooth_normals};
+
+// Boundary edges are held in place by planes perpendicular to their face, weighted by this much more than the faces themselves,
+// so open meshes like terrain patches keep their outline.
+const BOUNDARY_WEIGHT: f64 = 1000.0;
+// Collapses that turn a triangle by more than about 60 degrees are skipped, to keep the surface from folding over.
+const MAX_FOLD_COS: f64 = 0.5;
+// Simplified meshes have their normals recomputed, keeping edges sharper than this hard.
+const CREASE_ANGLE: Deg<f32> = Deg(60.0);
+
+type Point3d = Point3<f64>;
+type Vector3d = Vector3<f64>;
+
+// The sum of squared distances to a set of planes, as the symmetric matrix [a b c d]ᵀ[a b c d] of each plane summed up.
+// Stored as its upper triangle: aa ab ac ad bb bc bd cc cd dd.
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(normal: Vector3d, point: Point3d, weight: f64) -> Quadric {
+        let [a, b, c] = [normal.x, normal.y, normal.z];
+        let d = -normal.dot(Vector3d::new(point.x, point.y, point.z));
+        Quadric([a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d].map(|x| x * weight))
+    }
+    fn add(&mut self, other: &Quadric) {
+        for (x, y) in self.0.iter_mut().zip(other.0.iter()) {
+            *x += y;
+        }
+    }
+    fn error(&self, p: Point3d) -> f64 {
+        let q = &self.0;
+        q[0] * p.x * p.x + 2.0 * q[1] * p.x * p.y + 2.0 * q[2] * p.x * p.z + 2.0 * q[3] * p.x
+            + q[4] * p.y * p.y + 2.0 * q[5] * p.y * p.z + 2.0 * q[6] * p.y
+            + q[7] * p.z * p.z + 2.0 * q[8] * p.z
+            + q[9]
+    }
+    // The point with the least error, if the planes pin one down.
+    fn minimum(&self) -> Option<Point3d> {
+        let q = &self.0;
+        let det = q[0] * (q[4] * q[7] - q[5] * q[5]) - q[1] * (q[1] * q[7] - q[5] * q[2]) + q[2] * (q[1] * q[5] - q[4] * q[2]);
+        let scale = q[0] + q[4] + q[7];
+        if det.abs() <= 1e-9 * scale * scale * scale {
+            return None;
+        }
+        // Cramer's rule on the top left 3x3 block, solving for where the gradient is zero.
+        let (bx, by, bz) = (-q[3], -q[6], -q[8]);
+        let x = (bx * (q[4] * q[7] - q[5] * q[5]) - q[1] * (by * q[7] - q[5] * bz) + q[2] * (by * q[5] - q[4] * bz)) / det;
+        let y = (q[0] * (by * q[7] - q[5] * bz) - bx * (q[1] * q[7] - q[5] * q[2]) + q[2] * (q[1] * bz - by * q[2])) / det;
+        let z = (q[0] * (q[4] * bz - by * q[5]) - q[1] * (q[1] * bz - by * q[2]) + bx * (q[1] * q[5] - q[4] * q[2])) / det;
+        Some(Point3d::new(x, y, z))
+    }
+}
+
+// A candidate edge collapse. It's stale if either vertex has changed since it was queued.
+struct Collapse {
+    cost: f64,
+    vertices: [usize; 2],
+    versions: [u32; 2],
+    target: Point3d,
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cost.total_cmp(&other.cost) == Ordering::Equal
+    }
+}
+impl Eq for Collapse {}
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+// Reversed, so the heap pops the cheapest collapse first.
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+struct Simplifier {
+    positions: Vec<Point3d>,
+    quadrics: Vec<Quadric>,
+    versions: Vec<u32>,
+    removed: Vec<bool>,
+    boundary: Vec<bool>,
+    triangles: Vec<[usize; 3]>,
+    triangle_removed: Vec<bool>,
+    vertex_triangles: Vec<Vec<usize>>,
+    queue: BinaryHeap<Collapse>,
+}
+
+impl Simplifier {
+    fn normal(&self, triangle: [usize; 3]) -> Vector3d {
+        let [a, b, c] = triangle.map(|v| self.positions[v]);
+        (b - a).cross(c - a)
+    }
+    fn neighbours(&self, vertex: usize) -> Vec<usize> {
+        let mut neighbours: Vec<usize> = self.vertex_triangles[vertex].iter()
+            .flat_map(|t| self.triangles[*t])
+            .filter(|v| *v != vertex)
+            .collect();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        neighbours
+    }
+
+    fn queue_collapse(&mut self, v0: usize, v1: usize) {
+        let mut quadric = self.quadrics[v0];
+        quadric.add(&self.quadrics[v1]);
+        let (p0, p1) = (self.positions[v0], self.positions[v1]);
+        let midpoint = p0 + (p1 - p0) * 0.5;
+        let target = quadric.minimum()
+            .filter(|p| p.x.is_finite() && p.y.is_finite() && p.z.is_finite())
+            .unwrap_or_else(|| {
+                [p0, p1, midpoint].into_iter().min_by(|a, b| quadric.error(*a).total_cmp(&quadric.error(*b))).unwrap()
+            });
+        self.queue.push(Collapse {
+            cost: quadric.error(target),
+            vertices: [v0, v1],
+            versions: [self.versions[v0], self.versions[v1]],
+            target,
+        });
+    }
+
+    // Collapses v1 into v0 at `target`, unless that would fold a triangle over or pinch the surface into a non-manifold shape.
+    // Returns how many triangles were removed.
+    fn collapse(&mut self, v0: usize, v1: usize, target: Point3d) -> Option<usize> {
+        let shared: Vec<usize> = self.vertex_triangles[v0].iter().copied()
+            .filter(|t| self.triangles[*t].contains(&v1))
+            .collect();
+        let n0 = self.neighbours(v0);
+        let common = self.neighbours(v1).iter().filter(|v| n0.binary_search(v).is_ok()).count();
+        if shared.is_empty() || common != shared.len() {
+            return None;
+        }
+        // Joining two boundaries through the interior would pinch the surface, and collapsing the last triangles around a vertex would remove it.
+        if self.boundary[v0] && self.boundary[v1] && shared.len() > 1 {
+            return None;
+        }
+        if self.vertex_triangles[v0].len() + self.vertex_triangles[v1].len() == 2 * shared.len() {
+            return None;
+        }
+        for vertex in [v0, v1] {
+            for t in self.vertex_triangles[vertex].iter() {
+                if shared.contains(t) {
+                    continue;
+                }
+                let before = self.normal(self.triangles[*t]);
+                let mut moved = self.triangles[*t].map(|v| self.positions[v]);
+                for (corner, v) in self.triangles[*t].iter().enumerate() {
+                    if *v == vertex {
+                        moved[corner] = target;
+                    }
+                }
+                let after = (moved[1] - moved[0]).cross(moved[2] - moved[0]);
+                if after.magnitude2() == 0.0 || after.normalize().dot(before.normalize()) < MAX_FOLD_COS {
+                    return None;
+                }
+            }
+        }
+
+        self.positions[v0] = target;
+        let quadric = self.quadrics[v1];
+        self.quadrics[v0].add(&quadric);
+        self.removed[v1] = true;
+        self.boundary[v0] |= self.boundary[v1];
+        self.versions[v0] += 1;
+        for t in shared.iter() {
+            self.triangle_removed[*t] = true;
+            for v in self.triangles[*t] {
+                self.vertex_triangles[v].retain(|other| other != t);
+            }
+        }
+        for t in std::mem::take(&mut self.vertex_triangles[v1]) {
+            for v in self.triangles[t].iter_mut() {
+                if *v == v1 {
+                    *v = v0;
+                }
+            }
+            self.vertex_triangles[v0].push(t);
+        }
+        for neighbour in self.neighbours(v0) {
+            self.queue_collapse(v0, neighbour);
+        }
+        Some(shared.len())
+    }
+}
+
+// Reduces a mesh to about `target_triangles` triangles by collapsing the edges whose removal changes its shape least, measured with
+// quadric error metrics. Vertices at the same position are merged first, keeping the color and UV of the first, so UV seams
+// are lost, and normals are recomputed. Tangents are left for load_mesh to generate.
+// Boundary edges are kept in place, and collapses that would flip a triangle are skipped, so it may stop short of the target.
+pub fn simplify_mesh(mesh: &TriangleMesh, target_triangles: usize) -> TriangleMesh {
+    let mut welded = HashMap::new();
+    let mut positions = Vec::new();
+    let mut colors = Vec::new();
+    let mut uvs = Vec::new();
+    let remap: Vec<usize> = mesh.positions.iter().enumerate().map(|(index, position)| {
+        *welded.entry(position_key(*position)).or_insert_with(|| {
+            positions.push(Point3d::new(position[0] as f64, position[1] as f64, position[2] as f64));
+            colors.extend(mesh.colors.get(index));
+            uvs.extend(mesh.uvs.get(index));
+            positions.len() - 1
+        })
+    }).collect();
+    let triangles: Vec<[usize; 3]> = mesh.indices.chunks_exact(3)
+        .map(|triangle| [0, 1, 2].map(|i| remap[triangle[i] as usize]))
+        .filter(|[a, b, c]| a != b && b != c && c != a)
+        .collect();
+
+    let vertex_count = positions.len();
+    let mut simplifier = Simplifier {
+        positions,
+        quadrics: vec![Quadric::default(); vertex_count],
+        versions: vec![0; vertex_count],
+        removed: vec![false; vertex_count],
+        boundary: vec![false; vertex_count],
+        triangle_removed: vec![false; triangles.len()],
+        vertex_triangles: vec![Vec::new(); vertex_count],
+        triangles,
+        queue: BinaryHeap::new(),
+    };
+
+    let mut edges: HashMap<(usize, usize), (usize, usize)> = HashMap::new();
+    for (t, triangle) in simplifier.triangles.iter().enumerate() {
+        let normal = simplifier.normal(*triangle);
+        let area = normal.magnitude() / 2.0;
+        if area > 0.0 {
+            let plane = Quadric::from_plane(normal.normalize(), simplifier.positions[triangle[0]], area);
+            for v in triangle {
+                simplifier.quadrics[*v].add(&plane);
+            }
+        }
+        for i in 0..3 {
+            let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+            edges.entry((a.min(b), a.max(b))).or_insert((0, t)).0 += 1;
+        }
+        for v in triangle {
+            simplifier.vertex_triangles[*v].push(t);
+        }
+    }
+    for ((a, b), (count, t)) in edges.iter() {
+        if *count == 1 {
+            let normal = simplifier.normal(simplifier.triangles[*t]);
+            let edge = simplifier.positions[*b] - simplifier.positions[*a];
+            let perpendicular = edge.cross(normal);
+            if perpendicular.magnitude2() > 0.0 {
+                let plane = Quadric::from_plane(perpendicular.normalize(), simplifier.positions[*a], BOUNDARY_WEIGHT * edge.magnitude2());
+                simplifier.quadrics[*a].add(&plane);
+                simplifier.quadrics[*b].add(&plane);
+            }
+            simplifier.boundary[*a] = true;
+            simplifier.boundary[*b] = true;
+        }
+    }
+    for (a, b) in edges.keys() {
+        simplifier.queue_collapse(*a, *b);
+    }
+
+    let mut remaining = simplifier.triangles.len();
+    while remaining > target_triangles {
+        let collapse = match simplifier.queue.pop() {
+            Some(collapse) => collapse,
+            None => break,
+        };
+        let [v0, v1] = collapse.vertices;
+        if simplifier.removed[v0] || simplifier.removed[v1]
+            || collapse.versions != [simplifier.versions[v0], simplifier.versions[v1]] {
+            continue;
+        }
+        if let Some(removed) = simplifier.collapse(v0, v1, collapse.target) {
+            remaining -= removed;
+        }
+    }
+
+    let mut output = TriangleMesh::default();
+    let mut output_index = vec![u32::MAX; vertex_count];
+    for (triangle, removed) in simplifier.triangles.iter().zip(simplifier.triangle_removed.iter()) {
+        if *removed {
+            continue;
+        }
+        for v in triangle {
+            if output_index[*v] == u32::MAX {
+                output_index[*v] = output.positions.len() as u32;
+                let p = simplifier.positions[*v];
+                output.positions.push([p.x as f32, p.y as f32, p.z as f32]);
+                output.colors.extend(colors.get(*v));
+                output.uvs.extend(uvs.get(*v));
+            }
+            output.indices.push(output_index[*v]);
+        }
+    }
+    smooth_normals(&mut output, CREASE_ANGLE);
+    output
+}
+
+// Successively simplified copies of a mesh, each with about half the triangles of the one before.
+// Stops early once simplifying no longer removes many triangles.
+pub fn lod_chain(mesh: &TriangleMesh, levels: usize) -> Vec<TriangleMesh> {
+    let mut chain: Vec<TriangleMesh> = Vec::with_capacity(levels);
+    for _ in 0..levels {
+        let previous = chain.last().unwrap_or(mesh);
+        let triangles = previous.indices.len() / 3;
+        let simplified = simplify_mesh(previous, triangles / 2);
+        if simplified.indices.len() / 3 > triangles * 3 / 4 {
+            break;
+        }
+        chain.push(simplified);
+    }
+    chain
+}
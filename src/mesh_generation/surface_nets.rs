@@ -0,0 +1,84 @@
+use cgmath::{InnerSpace, Zero};
+use ndshape::{Shape, Shape3u32};
+
+use crate::{triangle_draw::TriangleMesh, transform::Vector3f};
+
+// Offsets of a cell's eight corners, with bit i of the corner's index set if it's offset along axis i.
+const CORNERS: [[u32; 3]; 8] = [[0, 0, 0], [1, 0, 0], [0, 1, 0], [1, 1, 0], [0, 0, 1], [1, 0, 1], [0, 1, 1], [1, 1, 1]];
+
+// Meshes the zero surface of a density field sampled at each point of a grid, with x varying fastest. Negative densities are solid
+// and positive ones are empty, as with a signed distance field. Each grid cell the surface passes through gets one vertex, at the
+// average of the points where the surface crosses its edges, and each grid edge it crosses gets a quad joining the four cells
+// around that edge. Normals point along the density's gradient, out of the solid.
+//
+// The surface isn't closed where solid touches the edge of the grid, so surround the field with empty samples to cap it.
+pub fn surface_nets(density: &[f32], shape: [u32; 3]) -> TriangleMesh {
+    let grid = Shape3u32::new(shape);
+    assert_eq!(density.len(), grid.size() as usize, "density field doesn't match its shape");
+    let mut mesh = TriangleMesh::default();
+    if shape.iter().any(|size| *size < 2) {
+        return mesh;
+    }
+    let cells = Shape3u32::new(shape.map(|size| size - 1));
+    let sample = |point: [u32; 3]| density[grid.linearize(point) as usize];
+
+    let mut cell_vertices = vec![u32::MAX; cells.size() as usize];
+    for index in 0..cells.size() {
+        let cell = cells.delinearize(index);
+        let corners = CORNERS.map(|offset| sample([cell[0] + offset[0], cell[1] + offset[1], cell[2] + offset[2]]));
+        let solid = corners.iter().filter(|value| **value < 0.0).count();
+        if solid == 0 || solid == 8 {
+            continue;
+        }
+        let mut sum = Vector3f::zero();
+        let mut crossings = 0;
+        let mut gradient = Vector3f::zero();
+        for (a, b) in (0..8).flat_map(|a| [1, 2, 4].map(|axis| (a, a | axis))).filter(|(a, b)| a != b) {
+            let (value_a, value_b) = (corners[a], corners[b]);
+            // Each edge is between two corners differing in one axis, so this also sums the density's change along each axis.
+            let axis = (a ^ b).trailing_zeros() as usize;
+            gradient[axis] += value_b - value_a;
+            if (value_a < 0.0) != (value_b < 0.0) {
+                let t = value_a / (value_a - value_b);
+                let [start, end] = [a, b].map(|corner| Vector3f::from(CORNERS[corner].map(|x| x as f32)));
+                sum += start + (end - start) * t;
+                crossings += 1;
+            }
+        }
+        let position = Vector3f::new(cell[0] as f32, cell[1] as f32, cell[2] as f32) + sum / crossings as f32;
+        let normal = if gradient.magnitude2() > 0.0 { gradient.normalize() } else { Vector3f::unit_y() };
+        cell_vertices[index as usize] = mesh.positions.len() as u32;
+        mesh.positions.push(position.into());
+        mesh.normals.push(normal.into());
+    }
+
+    // Edges on the grid's outer faces have fewer than four cells around them, so they're skipped.
+    for index in 0..grid.size() {
+        let point = grid.delinearize(index);
+        for axis in 0..3 {
+            let (b, c) = ((axis + 1) % 3, (axis + 2) % 3);
+            if point[axis] + 1 >= shape[axis] || point[b] == 0 || point[b] + 1 >= shape[b] || point[c] == 0 || point[c] + 1 >= shape[c] {
+                continue;
+            }
+            let mut next = point;
+            next[axis] += 1;
+            let solid = sample(point) < 0.0;
+            if solid == (sample(next) < 0.0) {
+                continue;
+            }
+            let cell = |db: u32, dc: u32| {
+                let mut cell = point;
+                cell[b] -= db;
+                cell[c] -= dc;
+                cell_vertices[cells.linearize(cell) as usize]
+            };
+            // Counter-clockwise looking down the edge from its positive end, so the quad faces along the edge, out of the solid.
+            let mut quad = [cell(1, 1), cell(0, 1), cell(0, 0), cell(1, 0)];
+            if !solid {
+                quad.reverse();
+            }
+            mesh.indices.extend([quad[0], quad[1], quad[2], quad[0], quad[2], quad[3]]);
+        }
+    }
+    mesh
+}
@@ -0,0 +1,70 @@
+use cgmath::{EuclideanSpace, InnerSpace, Vector2, Zero};
+
+use crate::transform::{Point3f, Vector3f};
+
+// Tolerance used when deciding whether polygon corners are convex, or points lie inside a triangle.
+const EPSILON: f32 = 1e-6;
+
+// Newell's method. The result points towards the side the polygon winds counter-clockwise around,
+// and is zero for degenerate polygons. It is not normalized.
+pub fn polygon_normal(vertices: &[Point3f]) -> Vector3f {
+    let mut normal = Vector3f::zero();
+    for i in 0..vertices.len() {
+        let current = vertices[i];
+        let next = vertices[(i + 1) % vertices.len()];
+        normal.x += (current.y - next.y) * (current.z + next.z);
+        normal.y += (current.z - next.z) * (current.x + next.x);
+        normal.z += (current.x - next.x) * (current.y + next.y);
+    }
+    normal
+}
+
+fn cross(a: Vector2<f32>, b: Vector2<f32>) -> f32 {
+    a.x * b.y - a.y * b.x
+}
+
+fn point_in_triangle(p: Vector2<f32>, a: Vector2<f32>, b: Vector2<f32>, c: Vector2<f32>) -> bool {
+    cross(b - a, p - a) >= -EPSILON && cross(c - b, p - b) >= -EPSILON && cross(a - c, p - c) >= -EPSILON
+}
+
+// Triangulates a planar or near-planar polygon by ear clipping, after projecting it onto the plane perpendicular to `normal`.
+// The vertices must wind counter-clockwise around `normal`, and the triangles returned do too.
+// Returns indices into `vertices`. Self-intersecting polygons are triangulated as far as possible, then fanned.
+pub fn triangulate_polygon(vertices: &[Point3f], normal: Vector3f) -> Vec<u32> {
+    let mut indices = Vec::with_capacity(vertices.len().saturating_sub(2) * 3);
+    if vertices.len() < 3 {
+        return indices;
+    }
+
+    let reference = if normal.x.abs() > 0.9 { Vector3f::unit_y() } else { Vector3f::unit_x() };
+    let u = normal.cross(reference).normalize();
+    let v = normal.cross(u);
+    let points: Vec<Vector2<f32>> = vertices.iter().map(|p| Vector2::new(p.to_vec().dot(u), p.to_vec().dot(v))).collect();
+
+    let mut remaining: Vec<usize> = (0..vertices.len()).collect();
+    while remaining.len() > 3 {
+        let count = remaining.len();
+        let ear = (0..count).find(|&i| {
+            let a = points[remaining[(i + count - 1) % count]];
+            let b = points[remaining[i]];
+            let c = points[remaining[(i + 1) % count]];
+            if cross(b - a, c - b) <= EPSILON {
+                return false;
+            }
+            remaining.iter().enumerate().all(|(j, &other)| {
+                j == i || j == (i + 1) % count || j == (i + count - 1) % count || !point_in_triangle(points[other], a, b, c)
+            })
+        });
+        match ear {
+            Some(i) => {
+                indices.extend([remaining[(i + count - 1) % count], remaining[i], remaining[(i + 1) % count]].iter().map(|&index| index as u32));
+                remaining.remove(i);
+            }
+            None => break,
+        }
+    }
+    for i in 2..remaining.len() {
+        indices.extend([remaining[0], remaining[i - 1], remaining[i]].iter().map(|&index| index as u32));
+    }
+    indices
+}
@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+
+use cgmath::{Angle, Deg, InnerSpace, MetricSpace, Rad, Zero};
+
+use crate::{triangle_draw::TriangleMesh, transform::{Point3f, Vector3f}};
+
+#[derive(Debug)]
+pub enum MeshError {
+    AttributeLength { attribute: &'static str, expected: usize, unexpected: usize },
+    IndexCount { unexpected: usize },
+    IndexOutOfBounds { triangle: usize, index: u32, vertices: usize },
+    NotFinite { attribute: &'static str, vertex: usize },
+}
+
+impl std::fmt::Display for MeshError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MeshError::AttributeLength { attribute, expected, unexpected } =>
+                write!(f, "Mesh has {} {} for {} vertices", unexpected, attribute, expected),
+            MeshError::IndexCount { unexpected } => write!(f, "Mesh has {} indices, which isn't a whole number of triangles", unexpected),
+            MeshError::IndexOutOfBounds { triangle, index, vertices } =>
+                write!(f, "Triangle {} uses vertex {}, but the mesh only has {} vertices", triangle, index, vertices),
+            MeshError::NotFinite { attribute, vertex } => write!(f, "Vertex {} has a non-finite {}", vertex, attribute),
+        }
+    }
+}
+
+impl std::error::Error for MeshError {}
+
+// Checks that a mesh is safe to upload: every vertex has a position and normal (and a color, UV or tangent, if any vertex does),
+// they're all finite, and every triangle's indices are in bounds.
+pub fn validate_mesh(mesh: &TriangleMesh) -> Result<(), MeshError> {
+    let vertices = mesh.positions.len();
+    if mesh.normals.len() != vertices {
+        return Err(MeshError::AttributeLength { attribute: "normals", expected: vertices, unexpected: mesh.normals.len() });
+    }
+    if !mesh.colors.is_empty() && mesh.colors.len() != vertices {
+        return Err(MeshError::AttributeLength { attribute: "colors", expected: vertices, unexpected: mesh.colors.len() });
+    }
+    if !mesh.uvs.is_empty() && mesh.uvs.len() != vertices {
+        return Err(MeshError::AttributeLength { attribute: "UVs", expected: vertices, unexpected: mesh.uvs.len() });
+    }
+    if !mesh.tangents.is_empty() && mesh.tangents.len() != vertices {
+        return Err(MeshError::AttributeLength { attribute: "tangents", expected: vertices, unexpected: mesh.tangents.len() });
+    }
+    if mesh.indices.len() % 3 != 0 {
+        return Err(MeshError::IndexCount { unexpected: mesh.indices.len() });
+    }
+    let attributes = [
+        ("position", mesh.positions.iter().position(|p| !p.iter().all(|x| x.is_finite()))),
+        ("normal", mesh.normals.iter().position(|n| !n.iter().all(|x| x.is_finite()))),
+        ("color", mesh.colors.iter().position(|c| !c.iter().all(|x| x.is_finite()))),
+        ("UV", mesh.uvs.iter().position(|uv| !uv.iter().all(|x| x.is_finite()))),
+        ("tangent", mesh.tangents.iter().position(|t| !t.iter().all(|x| x.is_finite()))),
+    ];
+    for (attribute, vertex) in attributes {
+        if let Some(vertex) = vertex {
+            return Err(MeshError::NotFinite { attribute, vertex });
+        }
+    }
+    if let Some(position) = mesh.indices.iter().position(|index| *index as usize >= vertices) {
+        return Err(MeshError::IndexOutOfBounds { triangle: position / 3, index: mesh.indices[position], vertices });
+    }
+    Ok(())
+}
+
+// Vertices are matched by position, so meshes with split normals or colors still have their shared corners found.
+pub(super) fn position_key(position: [f32; 3]) -> [u32; 3] {
+    // Adding zero turns -0.0 into 0.0, so they match.
+    position.map(|x| (x + 0.0).to_bits())
+}
+
+fn face_normal(mesh: &TriangleMesh, triangle: &[u32]) -> Vector3f {
+    let [a, b, c] = [0, 1, 2].map(|i| Point3f::from(mesh.positions[triangle[i] as usize]));
+    (b - a).cross(c - a)
+}
+
+// Merges vertices closer together than `epsilon`, keeping the attributes of the first of them. Vertices are renumbered in order of
+// their first occurrence. Triangles that collapse are left in place, so call remove_degenerate_triangles afterwards.
+pub fn weld_vertices(mesh: &mut TriangleMesh, epsilon: f32) {
+    assert!(epsilon > 0.0, "welding needs a positive epsilon");
+    let cell = |p: Point3f| [p.x, p.y, p.z].map(|x| (x / epsilon).floor() as i32);
+    let mut grid: HashMap<[i32; 3], Vec<u32>> = HashMap::new();
+    let mut remap = Vec::with_capacity(mesh.positions.len());
+    let mut welded = TriangleMesh::default();
+    for (index, position) in mesh.positions.iter().enumerate() {
+        let point = Point3f::from(*position);
+        let [x, y, z] = cell(point);
+        let mut found = None;
+        'search: for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    for candidate in grid.get(&[x + dx, y + dy, z + dz]).into_iter().flatten() {
+                        if Point3f::from(welded.positions[*candidate as usize]).distance2(point) <= epsilon * epsilon {
+                            found = Some(*candidate);
+                            break 'search;
+                        }
+                    }
+                }
+            }
+        }
+        let new_index = found.unwrap_or_else(|| {
+            let new_index = welded.positions.len() as u32;
+            welded.positions.push(*position);
+            welded.normals.extend(mesh.normals.get(index));
+            welded.colors.extend(mesh.colors.get(index));
+            welded.uvs.extend(mesh.uvs.get(index));
+            welded.tangents.extend(mesh.tangents.get(index));
+            grid.entry([x, y, z]).or_default().push(new_index);
+            new_index
+        });
+        remap.push(new_index);
+    }
+    welded.indices = mesh.indices.iter().map(|index| remap[*index as usize]).collect();
+    *mesh = welded;
+}
+
+// Removes triangles with repeated vertices or no area. Returns how many were removed.
+pub fn remove_degenerate_triangles(mesh: &mut TriangleMesh) -> usize {
+    let count = mesh.indices.len() / 3;
+    let mut indices = Vec::with_capacity(mesh.indices.len());
+    for triangle in mesh.indices.chunks_exact(3) {
+        let distinct = triangle[0] != triangle[1] && triangle[1] != triangle[2] && triangle[2] != triangle[0];
+        if distinct && face_normal(mesh, triangle).magnitude2() > f32::EPSILON * f32::EPSILON {
+            indices.extend_from_slice(triangle);
+        }
+    }
+    mesh.indices = indices;
+    count - mesh.indices.len() / 3
+}
+
+// Replaces the mesh's normals with the area-weighted average of the faces around each vertex. Faces meeting at an angle greater than
+// `crease_angle` don't share normals, so vertices on a crease are split into one per side. Faces are matched by vertex position,
+// and vertices no triangle uses are dropped. Tangents depend on the normals, so any the mesh had are generated again.
+pub fn smooth_normals(mesh: &mut TriangleMesh, crease_angle: Deg<f32>) {
+    let cos_crease = Rad::from(crease_angle).cos();
+    // The cross product's length is twice the triangle's area, which gives the weighting.
+    let face_normals: Vec<Vector3f> = mesh.indices.chunks_exact(3).map(|triangle| face_normal(mesh, triangle)).collect();
+    let mut faces_at: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+    for (corner, index) in mesh.indices.iter().enumerate() {
+        faces_at.entry(position_key(mesh.positions[*index as usize])).or_default().push(corner / 3);
+    }
+
+    let mut split: HashMap<(u32, [u32; 3]), u32> = HashMap::new();
+    let mut smoothed = TriangleMesh { indices: Vec::with_capacity(mesh.indices.len()), ..TriangleMesh::default() };
+    for (corner, index) in mesh.indices.iter().enumerate() {
+        let face = face_normals[corner / 3];
+        let mut normal = Vector3f::zero();
+        for other in faces_at[&position_key(mesh.positions[*index as usize])].iter() {
+            let other = face_normals[*other];
+            if face.is_zero() || other.is_zero() || face.normalize().dot(other.normalize()) >= cos_crease {
+                normal += other;
+            }
+        }
+        let normal: [f32; 3] = if normal.magnitude2() > 0.0 { normal.normalize() } else { Vector3f::zero() }.into();
+        let new_index = *split.entry((*index, position_key(normal))).or_insert_with(|| {
+            smoothed.positions.push(mesh.positions[*index as usize]);
+            smoothed.normals.push(normal);
+            smoothed.colors.extend(mesh.colors.get(*index as usize));
+            smoothed.uvs.extend(mesh.uvs.get(*index as usize));
+            smoothed.positions.len() as u32 - 1
+        });
+        smoothed.indices.push(new_index);
+    }
+    if !mesh.tangents.is_empty() {
+        generate_tangents(&mut smoothed);
+    }
+    *mesh = smoothed;
+}
+
+// Any unit vector perpendicular to `normal`.
+fn perpendicular(normal: Vector3f) -> Vector3f {
+    let axis = if normal.x.abs() < 0.9 { Vector3f::unit_x() } else { Vector3f::unit_y() };
+    (axis - normal * normal.dot(axis)).normalize()
+}
+
+// Computes tangents from the mesh's UVs, the way MikkTSpace does: each triangle's tangent and bitangent are weighted by the angle
+// of its corner at a vertex, and the sum is made perpendicular to the vertex's normal. Unlike MikkTSpace, vertices aren't split
+// where the UVs are mirrored, so give mirrored halves of a mesh their own vertices. Meshes without UVs get no tangents.
+pub fn generate_tangents(mesh: &mut TriangleMesh) {
+    mesh.tangents.clear();
+    if mesh.uvs.len() != mesh.positions.len() {
+        return;
+    }
+    let mut tangents = vec![Vector3f::zero(); mesh.positions.len()];
+    let mut bitangents = vec![Vector3f::zero(); mesh.positions.len()];
+    for triangle in mesh.indices.chunks_exact(3) {
+        let points = [0, 1, 2].map(|i| Point3f::from(mesh.positions[triangle[i] as usize]));
+        let uvs = [0, 1, 2].map(|i| mesh.uvs[triangle[i] as usize]);
+        let (edge1, edge2) = (points[1] - points[0], points[2] - points[0]);
+        let (du1, dv1) = (uvs[1][0] - uvs[0][0], uvs[1][1] - uvs[0][1]);
+        let (du2, dv2) = (uvs[2][0] - uvs[0][0], uvs[2][1] - uvs[0][1]);
+        let determinant = du1 * dv2 - du2 * dv1;
+        if determinant.abs() < f32::EPSILON {
+            continue;
+        }
+        let tangent = (edge1 * dv2 - edge2 * dv1) / determinant;
+        let bitangent = (edge2 * du1 - edge1 * du2) / determinant;
+        for i in 0..3 {
+            let (to_next, to_previous) = (points[(i + 1) % 3] - points[i], points[(i + 2) % 3] - points[i]);
+            if to_next.magnitude2() == 0.0 || to_previous.magnitude2() == 0.0 {
+                continue;
+            }
+            let angle = to_next.angle(to_previous).0;
+            tangents[triangle[i] as usize] += tangent * angle;
+            bitangents[triangle[i] as usize] += bitangent * angle;
+        }
+    }
+    mesh.tangents = mesh.normals.iter().enumerate().map(|(index, normal)| {
+        let normal = Vector3f::from(*normal);
+        let tangent = tangents[index] - normal * normal.dot(tangents[index]);
+        // Vertices whose triangles have no area in UV space still need a tangent perpendicular to their normal.
+        let tangent = if tangent.magnitude2() > f32::EPSILON * f32::EPSILON { tangent.normalize() } else { perpendicular(normal) };
+        let sign = if normal.cross(tangent).dot(bitangents[index]) < 0.0 { -1.0 } else { 1.0 };
+        [tangent.x, tangent.y, tangent.z, sign]
+    }).collect();
+}
@@ -0,0 +1,60 @@
+use std::path::Path;
+
+use gltf::mesh::Mode;
+
+use crate::{mesh_generation::smooth_normals, triangle_draw::TriangleMesh};
+
+use super::{CREASE_ANGLE, Scene, SceneMaterial, SceneMesh};
+
+fn invalid_data(error: gltf::Error) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, error)
+}
+
+// Reads a glTF 2.0 file, either .gltf with embedded or separate buffers, or binary .glb. Each triangle primitive becomes a mesh,
+// named after its mesh, with the primitive's index appended if the mesh has more than one. Only the first set of texture coordinates
+// is read. Node transforms and textures are ignored.
+pub fn read_gltf(path: &Path) -> std::io::Result<Scene> {
+    let (document, buffers, _images) = gltf::import(path).map_err(invalid_data)?;
+    let mut scene = Scene::default();
+
+    for material in document.materials() {
+        let name = material.name().map_or_else(|| format!("material{}", scene.materials.len()), str::to_string);
+        scene.materials.push(SceneMaterial { name, color: material.pbr_metallic_roughness().base_color_factor() });
+    }
+
+    for mesh in document.meshes() {
+        let name = mesh.name().map_or_else(|| format!("mesh{}", mesh.index()), str::to_string);
+        let primitive_count = mesh.primitives().len();
+        for primitive in mesh.primitives() {
+            if primitive.mode() != Mode::Triangles {
+                continue;
+            }
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let positions: Vec<[f32; 3]> = match reader.read_positions() {
+                Some(positions) => positions.collect(),
+                None => continue,
+            };
+            let indices = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+            let normals = reader.read_normals();
+            let has_normals = normals.is_some();
+            let mut triangle_mesh = TriangleMesh {
+                normals: normals.map_or_else(Vec::new, |normals| normals.collect()),
+                colors: reader.read_colors(0).map_or_else(Vec::new, |colors| colors.into_rgba_f32().collect()),
+                uvs: reader.read_tex_coords(0).map_or_else(Vec::new, |uvs| uvs.into_f32().collect()),
+                tangents: reader.read_tangents().map_or_else(Vec::new, |tangents| tangents.collect()),
+                positions,
+                indices,
+            };
+            if !has_normals {
+                smooth_normals(&mut triangle_mesh, CREASE_ANGLE);
+            }
+            let name = if primitive_count > 1 { format!("{}.{}", name, primitive.index()) } else { name.clone() };
+            let material = primitive.material().index();
+            scene.meshes.push(SceneMesh { name, material, mesh: triangle_mesh });
+        }
+    }
+    Ok(scene)
+}
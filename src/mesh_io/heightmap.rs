@@ -0,0 +1,174 @@
+use std::{fs::File, io::{BufRead, BufReader, BufWriter, Read, Write}, path::Path};
+
+use super::extension;
+
+// A grid of heights, with x varying fastest, as in TerrainPatch. Images store it with x along each row and one row per z.
+#[derive(Clone, Debug)]
+pub struct Heightmap {
+    pub size: [u32; 2],
+    pub heights: Vec<f32>,
+}
+
+// How heights map onto the values stored in a file. A height is `offset + scale * value`, where value runs from 0 to 1 across
+// an image's range of gray levels, or is the stored float in a raw file.
+#[derive(Clone, Copy, Debug)]
+pub struct HeightScale {
+    pub scale: f32,
+    pub offset: f32,
+}
+
+impl Default for HeightScale {
+    fn default() -> HeightScale {
+        HeightScale { scale: 1.0, offset: 0.0 }
+    }
+}
+
+fn invalid_data(message: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+fn unsupported_extension(path: &Path) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("unsupported heightmap file extension: {}", path.display()))
+}
+
+// Reads a heightmap from a 16-bit (or 8-bit) grayscale PGM or PNG, or a square raw file of little-endian 32-bit floats,
+// chosen by the path's extension (.pgm, .png, or .raw or .r32). Colored PNGs are read from their first channel.
+pub fn load_heightmap<P: AsRef<Path>>(path: P, scale: HeightScale) -> std::io::Result<Heightmap> {
+    let path = path.as_ref();
+    let (size, values) = match extension(path).as_deref() {
+        Some("pgm") => read_pgm(BufReader::new(File::open(path)?))?,
+        Some("png") => read_png(BufReader::new(File::open(path)?))?,
+        Some("raw") | Some("r32") => read_raw(BufReader::new(File::open(path)?))?,
+        _ => return Err(unsupported_extension(path)),
+    };
+    Ok(Heightmap { size, heights: values.iter().map(|value| scale.offset + scale.scale * value).collect() })
+}
+
+// Writes a heightmap in the format matching the path's extension, as load_heightmap reads it. Images are written as 16-bit
+// grayscale, and heights outside the range `scale` gives them are clamped. Returns how many heights were clamped, which is
+// always zero for raw files. Only square heightmaps can be written raw.
+pub fn save_heightmap<P: AsRef<Path>>(heightmap: &Heightmap, path: P, scale: HeightScale) -> std::io::Result<usize> {
+    let path = path.as_ref();
+    let values = heightmap.heights.iter().map(|height| (height - scale.offset) / scale.scale);
+    let format = extension(path);
+    if let Some("raw") | Some("r32") = format.as_deref() {
+        if heightmap.size[0] != heightmap.size[1] {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "raw heightmaps have no header, so they must be square"));
+        }
+        write_raw(values, BufWriter::new(File::create(path)?))?;
+        return Ok(0);
+    }
+    let mut clamped = 0;
+    let levels: Vec<u16> = values.map(|value| {
+        if !(0.0..=1.0).contains(&value) {
+            clamped += 1;
+        }
+        (value.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16
+    }).collect();
+    match format.as_deref() {
+        Some("pgm") => write_pgm(heightmap.size, &levels, BufWriter::new(File::create(path)?))?,
+        Some("png") => write_png(heightmap.size, &levels, BufWriter::new(File::create(path)?))?,
+        _ => return Err(unsupported_extension(path)),
+    }
+    Ok(clamped)
+}
+
+// The next whitespace separated word of a PGM header, skipping comments.
+fn pgm_word<R: BufRead>(reader: &mut R) -> std::io::Result<String> {
+    let mut word = String::new();
+    let mut byte = [0];
+    loop {
+        reader.read_exact(&mut byte)?;
+        match byte[0] {
+            b'#' if word.is_empty() => {
+                reader.read_until(b'\n', &mut Vec::new())?;
+            }
+            b if b.is_ascii_whitespace() => if !word.is_empty() {
+                return Ok(word);
+            },
+            b => word.push(b as char),
+        }
+    }
+}
+
+// Binary (P5) PGM, with samples of one byte, or two big-endian bytes if the maximum value is over 255.
+fn read_pgm<R: BufRead>(mut reader: R) -> std::io::Result<([u32; 2], Vec<f32>)> {
+    if pgm_word(&mut reader)? != "P5" {
+        return Err(invalid_data("not a binary PGM file"));
+    }
+    let mut number = || pgm_word(&mut reader)?.parse::<u32>().map_err(|_| invalid_data("invalid PGM header"));
+    let (width, height, max) = (number()?, number()?, number()?);
+    if max == 0 || max > u16::MAX as u32 {
+        return Err(invalid_data("invalid PGM maximum value"));
+    }
+    // The header can't be trusted to size an allocation, so the samples are read first and then checked against it.
+    let sample_size = if max > 255 { 2 } else { 1 };
+    let length = (width as usize).checked_mul(height as usize).and_then(|count| count.checked_mul(sample_size));
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    match length {
+        Some(length) if length <= bytes.len() => bytes.truncate(length),
+        _ => return Err(invalid_data("PGM file is too short for the size in its header")),
+    }
+    let values = if max > 255 {
+        bytes.chunks_exact(2).map(|sample| u16::from_be_bytes([sample[0], sample[1]]) as f32 / max as f32).collect()
+    } else {
+        bytes.iter().map(|sample| *sample as f32 / max as f32).collect()
+    };
+    Ok(([width, height], values))
+}
+
+fn write_pgm<W: Write>(size: [u32; 2], levels: &[u16], mut writer: W) -> std::io::Result<()> {
+    write!(writer, "P5\n{} {}\n{}\n", size[0], size[1], u16::MAX)?;
+    for level in levels {
+        writer.write_all(&level.to_be_bytes())?;
+    }
+    writer.flush()
+}
+
+fn read_png<R: Read>(reader: R) -> std::io::Result<([u32; 2], Vec<f32>)> {
+    let mut decoder = png::Decoder::new(reader);
+    // Expands palettes and gray levels of under 8 bits, but keeps 16-bit samples.
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(invalid_data)?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(invalid_data)?;
+    let channels = info.color_type.samples();
+    let values = match info.bit_depth {
+        png::BitDepth::Sixteen => buffer[..info.line_size * info.height as usize].chunks_exact(2 * channels)
+            .map(|pixel| u16::from_be_bytes([pixel[0], pixel[1]]) as f32 / u16::MAX as f32)
+            .collect(),
+        _ => buffer[..info.line_size * info.height as usize].chunks_exact(channels)
+            .map(|pixel| pixel[0] as f32 / u8::MAX as f32)
+            .collect(),
+    };
+    Ok(([info.width, info.height], values))
+}
+
+fn write_png<W: Write>(size: [u32; 2], levels: &[u16], writer: W) -> std::io::Result<()> {
+    let mut encoder = png::Encoder::new(writer, size[0], size[1]);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Sixteen);
+    let bytes: Vec<u8> = levels.iter().flat_map(|level| level.to_be_bytes()).collect();
+    encoder.write_header().and_then(|mut writer| writer.write_image_data(&bytes)).map_err(invalid_data)
+}
+
+// Raw files have no header, so they're assumed to be square.
+fn read_raw<R: Read>(mut reader: R) -> std::io::Result<([u32; 2], Vec<f32>)> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let count = bytes.len() / 4;
+    let side = (count as f64).sqrt().round() as usize;
+    if bytes.len() % 4 != 0 || side.checked_mul(side) != Some(count) {
+        return Err(invalid_data("raw heightmap isn't a square of 32-bit floats"));
+    }
+    let side = u32::try_from(side).map_err(|_| invalid_data("raw heightmap is too large"))?;
+    Ok(([side, side], bytes.chunks_exact(4).map(|value| f32::from_le_bytes([value[0], value[1], value[2], value[3]])).collect()))
+}
+
+fn write_raw<W: Write>(values: impl Iterator<Item = f32>, mut writer: W) -> std::io::Result<()> {
+    for value in values {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    writer.flush()
+}
@@ -0,0 +1,64 @@
+mod gltf;
+mod heightmap;
+mod obj;
+mod ply;
+
+use std::{fs::File, io::BufWriter, path::Path};
+
+use cgmath::Deg;
+
+use crate::triangle_draw::TriangleMesh;
+
+pub use self::gltf::read_gltf;
+pub use heightmap::{Heightmap, HeightScale, load_heightmap, save_heightmap};
+pub use obj::{read_obj, write_obj};
+pub use ply::{write_ply, PlyFormat};
+
+// Meshes read without normals get smooth ones, keeping edges sharper than this hard.
+const CREASE_ANGLE: Deg<f32> = Deg(60.0);
+
+// A mesh read from a file. `material` indexes into the scene's materials, and is None if the file didn't give it one.
+pub struct SceneMesh {
+    pub name: String,
+    pub material: Option<usize>,
+    pub mesh: TriangleMesh,
+}
+
+pub struct SceneMaterial {
+    pub name: String,
+    pub color: [f32; 4],
+}
+
+#[derive(Default)]
+pub struct Scene {
+    pub meshes: Vec<SceneMesh>,
+    pub materials: Vec<SceneMaterial>,
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_ascii_lowercase())
+}
+
+fn unsupported_extension(path: &Path) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("unsupported mesh file extension: {}", path.display()))
+}
+
+// Writes the mesh in the format matching the path's extension (.obj or .ply). PLY files are written in binary.
+pub fn save_mesh<P: AsRef<Path>>(mesh: &TriangleMesh, path: P) -> std::io::Result<()> {
+    let path = path.as_ref();
+    match extension(path).as_deref() {
+        Some("obj") => write_obj(mesh, BufWriter::new(File::create(path)?)),
+        Some("ply") => write_ply(mesh, BufWriter::new(File::create(path)?), PlyFormat::BinaryLittleEndian),
+        _ => Err(unsupported_extension(path)),
+    }
+}
+
+// Reads the meshes and materials in a file, in the format matching the path's extension (.obj, .gltf or .glb).
+pub fn load_scene<P: AsRef<Path>>(path: P) -> std::io::Result<Scene> {
+    let path = path.as_ref();
+    match extension(path).as_deref() {
+        Some("obj") => read_obj(path),
+        Some("gltf") | Some("glb") => read_gltf(path),
+        _ => Err(unsupported_extension(path)),
+    }
+}
@@ -0,0 +1,206 @@
+use std::{collections::HashMap, io::Write, path::Path};
+
+use cgmath::InnerSpace;
+
+use crate::{mesh_generation::{polygon_normal, smooth_normals, triangulate_polygon}, transform::Point3f, triangle_draw::TriangleMesh};
+
+use super::{CREASE_ANGLE, Scene, SceneMaterial, SceneMesh};
+
+// Vertex colors are written after the position, which Blender and MeshLab both understand.
+pub fn write_obj<W: Write>(mesh: &TriangleMesh, mut writer: W) -> std::io::Result<()> {
+    let has_colors = mesh.colors.len() == mesh.positions.len();
+    let has_normals = mesh.normals.len() == mesh.positions.len();
+    let has_uvs = mesh.uvs.len() == mesh.positions.len();
+    for (index, position) in mesh.positions.iter().enumerate() {
+        write!(writer, "v {} {} {}", position[0], position[1], position[2])?;
+        if has_colors {
+            let color = mesh.colors[index];
+            write!(writer, " {} {} {}", color[0], color[1], color[2])?;
+        }
+        writeln!(writer)?;
+    }
+    if has_uvs {
+        for uv in mesh.uvs.iter() {
+            writeln!(writer, "vt {} {}", uv[0], uv[1])?;
+        }
+    }
+    if has_normals {
+        for normal in mesh.normals.iter() {
+            writeln!(writer, "vn {} {} {}", normal[0], normal[1], normal[2])?;
+        }
+    }
+    for triangle in mesh.indices.chunks_exact(3) {
+        write!(writer, "f")?;
+        for index in triangle {
+            // OBJ indices start at 1.
+            let index = index + 1;
+            match (has_uvs, has_normals) {
+                (true, true) => write!(writer, " {}/{}/{}", index, index, index)?,
+                (true, false) => write!(writer, " {}/{}", index, index)?,
+                (false, true) => write!(writer, " {}//{}", index, index)?,
+                (false, false) => write!(writer, " {}", index)?,
+            }
+        }
+        writeln!(writer)?;
+    }
+    writer.flush()
+}
+
+fn invalid_data(line: usize, message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("line {}: {}", line, message))
+}
+
+fn parse_floats<const N: usize>(words: &mut std::str::SplitWhitespace, line: usize) -> std::io::Result<[f32; N]> {
+    let mut values = [0.0; N];
+    for value in values.iter_mut() {
+        *value = words.next().and_then(|word| word.parse().ok()).ok_or_else(|| invalid_data(line, "expected a number"))?;
+    }
+    Ok(values)
+}
+
+// OBJ indices start at 1, and negative ones count back from the last element defined so far.
+fn parse_index(word: Option<&str>, count: usize, line: usize) -> std::io::Result<Option<usize>> {
+    let word = match word {
+        Some(word) if !word.is_empty() => word,
+        _ => return Ok(None),
+    };
+    let index: i64 = word.parse().map_err(|_| invalid_data(line, "invalid index"))?;
+    let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(invalid_data(line, "index out of range"));
+    }
+    Ok(Some(resolved as usize))
+}
+
+// Reads the base colors (Kd and d) of the materials in an MTL file.
+fn read_mtl(path: &Path, materials: &mut Vec<SceneMaterial>) -> std::io::Result<()> {
+    let text = std::fs::read_to_string(path)?;
+    for (number, line) in text.lines().enumerate() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("newmtl") => materials.push(SceneMaterial { name: words.collect::<Vec<_>>().join(" "), color: [1.0; 4] }),
+            Some("Kd") => if let Some(material) = materials.last_mut() {
+                let [r, g, b] = parse_floats(&mut words, number + 1)?;
+                material.color = [r, g, b, material.color[3]];
+            },
+            Some("d") => if let Some(material) = materials.last_mut() {
+                material.color[3] = parse_floats::<1>(&mut words, number + 1)?[0];
+            },
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+// A mesh being built from the faces of one object that use one material.
+struct ObjGroup {
+    name: String,
+    material: Option<usize>,
+    mesh: TriangleMesh,
+    vertices: HashMap<(usize, Option<usize>, Option<usize>), u32>,
+    missing_normals: bool,
+    missing_uvs: bool,
+}
+
+// Reads an OBJ file, and the MTL files it refers to. Faces are split into one mesh per object (o or g) and material.
+// Polygons are triangulated, and meshes with faces lacking normals have smooth normals computed instead. Meshes with faces
+// lacking texture coordinates get no UVs.
+pub fn read_obj(path: &Path) -> std::io::Result<Scene> {
+    let text = std::fs::read_to_string(path)?;
+    let mut scene = Scene::default();
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+    let mut colors: Vec<[f32; 4]> = Vec::new();
+    let mut normals: Vec<[f32; 3]> = Vec::new();
+    let mut uvs: Vec<[f32; 2]> = Vec::new();
+    let mut groups: Vec<ObjGroup> = Vec::new();
+    let mut object = String::from("default");
+    let mut material = None;
+    let mut current = None;
+
+    for (number, line) in text.lines().enumerate() {
+        let number = number + 1;
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("v") => {
+                let [x, y, z] = parse_floats(&mut words, number)?;
+                positions.push([x, y, z]);
+                // Vertex colors are an extension, written after the position.
+                let color: Vec<f32> = words.filter_map(|word| word.parse().ok()).collect();
+                colors.push(if color.len() >= 3 { [color[0], color[1], color[2], 1.0] } else { [1.0; 4] });
+            }
+            Some("vn") => normals.push(parse_floats(&mut words, number)?),
+            Some("vt") => uvs.push(parse_floats(&mut words, number)?),
+            Some("o") | Some("g") => {
+                object = words.collect::<Vec<_>>().join(" ");
+                current = None;
+            }
+            Some("usemtl") => {
+                let name = words.collect::<Vec<_>>().join(" ");
+                material = scene.materials.iter().position(|material| material.name == name);
+                current = None;
+            }
+            Some("mtllib") => {
+                let library = path.with_file_name(words.collect::<Vec<_>>().join(" "));
+                read_mtl(&library, &mut scene.materials)?;
+            }
+            Some("f") => {
+                let group = *current.get_or_insert_with(|| {
+                    groups.iter().position(|group| group.name == object && group.material == material).unwrap_or_else(|| {
+                        groups.push(ObjGroup {
+                            name: object.clone(),
+                            material,
+                            mesh: TriangleMesh::default(),
+                            vertices: HashMap::new(),
+                            missing_normals: false,
+                            missing_uvs: false,
+                        });
+                        groups.len() - 1
+                    })
+                });
+                let group = &mut groups[group];
+                let mut corners = Vec::new();
+                for word in words {
+                    let mut parts = word.split('/');
+                    let position = parse_index(parts.next(), positions.len(), number)?.ok_or_else(|| invalid_data(number, "face vertex without a position"))?;
+                    let uv = parse_index(parts.next(), uvs.len(), number)?;
+                    let normal = parse_index(parts.next(), normals.len(), number)?;
+                    group.missing_normals |= normal.is_none();
+                    group.missing_uvs |= uv.is_none();
+                    let mesh = &mut group.mesh;
+                    corners.push(*group.vertices.entry((position, uv, normal)).or_insert_with(|| {
+                        mesh.positions.push(positions[position]);
+                        mesh.normals.push(normal.map_or([0.0; 3], |normal| normals[normal]));
+                        mesh.uvs.push(uv.map_or([0.0; 2], |uv| uvs[uv]));
+                        mesh.colors.push(colors[position]);
+                        mesh.positions.len() as u32 - 1
+                    }));
+                }
+                if corners.len() < 3 {
+                    return Err(invalid_data(number, "face with fewer than 3 vertices"));
+                }
+                let points: Vec<Point3f> = corners.iter().map(|corner| Point3f::from(group.mesh.positions[*corner as usize])).collect();
+                let normal = polygon_normal(&points);
+                if normal.magnitude2() == 0.0 {
+                    continue;
+                }
+                let triangles = triangulate_polygon(&points, normal.normalize());
+                group.mesh.indices.extend(triangles.iter().map(|index| corners[*index as usize]));
+            }
+            _ => (),
+        }
+    }
+
+    for mut group in groups {
+        if group.mesh.colors.iter().all(|color| *color == [1.0; 4]) {
+            group.mesh.colors.clear();
+        }
+        if group.missing_uvs {
+            group.mesh.uvs.clear();
+        }
+        if group.missing_normals {
+            smooth_normals(&mut group.mesh, CREASE_ANGLE);
+        }
+        scene.meshes.push(SceneMesh { name: group.name, material: group.material, mesh: group.mesh });
+    }
+    Ok(scene)
+}
@@ -0,0 +1,80 @@
+use std::io::Write;
+
+use crate::triangle_draw::TriangleMesh;
+
+#[derive(Clone, Copy, Debug)]
+pub enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+}
+
+fn color_byte(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+pub fn write_ply<W: Write>(mesh: &TriangleMesh, mut writer: W, format: PlyFormat) -> std::io::Result<()> {
+    let has_colors = mesh.colors.len() == mesh.positions.len();
+    let has_normals = mesh.normals.len() == mesh.positions.len();
+    let has_uvs = mesh.uvs.len() == mesh.positions.len();
+
+    writeln!(writer, "ply")?;
+    match format {
+        PlyFormat::Ascii => writeln!(writer, "format ascii 1.0")?,
+        PlyFormat::BinaryLittleEndian => writeln!(writer, "format binary_little_endian 1.0")?,
+    }
+    writeln!(writer, "element vertex {}", mesh.positions.len())?;
+    writeln!(writer, "property float x\nproperty float y\nproperty float z")?;
+    if has_normals {
+        writeln!(writer, "property float nx\nproperty float ny\nproperty float nz")?;
+    }
+    if has_uvs {
+        writeln!(writer, "property float s\nproperty float t")?;
+    }
+    if has_colors {
+        writeln!(writer, "property uchar red\nproperty uchar green\nproperty uchar blue\nproperty uchar alpha")?;
+    }
+    writeln!(writer, "element face {}", mesh.indices.len() / 3)?;
+    writeln!(writer, "property list uchar uint vertex_indices")?;
+    writeln!(writer, "end_header")?;
+
+    for index in 0..mesh.positions.len() {
+        let mut floats = mesh.positions[index].to_vec();
+        if has_normals {
+            floats.extend_from_slice(&mesh.normals[index]);
+        }
+        if has_uvs {
+            floats.extend_from_slice(&mesh.uvs[index]);
+        }
+        let color = if has_colors { mesh.colors[index].map(color_byte) } else { [0; 4] };
+        match format {
+            PlyFormat::Ascii => {
+                let values: Vec<String> = floats.iter().map(|v| v.to_string()).collect();
+                write!(writer, "{}", values.join(" "))?;
+                if has_colors {
+                    write!(writer, " {} {} {} {}", color[0], color[1], color[2], color[3])?;
+                }
+                writeln!(writer)?;
+            }
+            PlyFormat::BinaryLittleEndian => {
+                for value in floats.iter() {
+                    writer.write_all(&value.to_le_bytes())?;
+                }
+                if has_colors {
+                    writer.write_all(&color)?;
+                }
+            }
+        }
+    }
+    for triangle in mesh.indices.chunks_exact(3) {
+        match format {
+            PlyFormat::Ascii => writeln!(writer, "3 {} {} {}", triangle[0], triangle[1], triangle[2])?,
+            PlyFormat::BinaryLittleEndian => {
+                writer.write_all(&[3])?;
+                for index in triangle.iter() {
+                    writer.write_all(&index.to_le_bytes())?;
+                }
+            }
+        }
+    }
+    writer.flush()
+}
@@ -0,0 +1,188 @@
+// Seeded gradient noise for generating terrain. Everything here is a pure function of its seed and coordinates, so the same
+// seed always gives the same terrain, and patches sampling neighbouring coordinates join up.
+
+use std::f64::consts::{PI, SQRT_2};
+
+// Mixes a seed and lattice point into a well distributed hash, with SplitMix64's finalizer.
+fn hash(seed: u64, x: i64, y: i64) -> u64 {
+    let mut z = seed
+        ^ (x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+// Dot product of a lattice point's random unit gradient with the offset (dx, dy) from it.
+fn gradient(seed: u64, x: i64, y: i64, dx: f64, dy: f64) -> f64 {
+    let angle = hash(seed, x, y) as f64 / u64::MAX as f64 * 2.0 * PI;
+    angle.cos() * dx + angle.sin() * dy
+}
+
+// 6t^5 - 15t^4 + 10t^3, which eases in and out with zero first and second derivatives at 0 and 1.
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+// Perlin's gradient noise, between -1 and 1. It's 0 at every integer point, and features are about one unit across.
+pub fn perlin(seed: u64, x: f64, y: f64) -> f64 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (ix, iy) = (x0 as i64, y0 as i64);
+    let (dx, dy) = (x - x0, y - y0);
+    let near = lerp(gradient(seed, ix, iy, dx, dy), gradient(seed, ix + 1, iy, dx - 1.0, dy), fade(dx));
+    let far = lerp(gradient(seed, ix, iy + 1, dx, dy - 1.0), gradient(seed, ix + 1, iy + 1, dx - 1.0, dy - 1.0), fade(dx));
+    // Unit gradients give values up to sqrt(1/2) in magnitude.
+    (lerp(near, far, fade(dy)) * SQRT_2).clamp(-1.0, 1.0)
+}
+
+// How far a layer of noise is shifted, by between a quarter and three quarters of a unit on each axis, depending on its seed.
+// Doubling the frequency maps integer points onto integer points, so without this every layer would be 0 at the same places.
+fn lattice_offset(seed: u64) -> (f64, f64) {
+    let fraction = |hash: u64| 0.25 + 0.5 * (hash as f64 / u64::MAX as f64);
+    (fraction(hash(seed, 1, 0)), fraction(hash(seed, 0, 1)))
+}
+
+// Sums `octaves` layers of noise, each with twice the frequency and half the amplitude of the one before, each seeded and offset
+// differently. `layer` maps each layer's noise. The result is divided by the total amplitude, so it stays in the range `layer` returns.
+fn sum_octaves(seed: u64, x: f64, y: f64, octaves: u32, layer: impl Fn(f64) -> f64) -> f64 {
+    let (mut total, mut amplitude, mut frequency, mut amplitudes) = (0.0, 1.0, 1.0, 0.0);
+    for octave in 0..octaves.max(1) {
+        let layer_seed = seed.wrapping_add(octave as u64);
+        let (offset_x, offset_y) = lattice_offset(layer_seed);
+        total += amplitude * layer(perlin(layer_seed, x * frequency + offset_x, y * frequency + offset_y));
+        amplitudes += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    total / amplitudes
+}
+
+// Fractional Brownian motion: rolling hills with detail at every scale, between -1 and 1.
+pub fn fbm(seed: u64, x: f64, y: f64, octaves: u32) -> f64 {
+    sum_octaves(seed, x, y, octaves, |noise| noise)
+}
+
+// Sharp ridges where the noise crosses zero, like mountain ranges, between 0 and 1.
+pub fn ridged(seed: u64, x: f64, y: f64, octaves: u32) -> f64 {
+    sum_octaves(seed, x, y, octaves, |noise| (1.0 - noise.abs()).powi(2))
+}
+
+// Flattens a height down onto the terrace below it, with terraces `step` apart. Heights already on a terrace stay where they are,
+// and the slopes between terraces are left to the mesh joining neighbouring samples on different levels.
+pub fn terrace(height: f64, step: f64) -> f64 {
+    if step <= 0.0 {
+        return height;
+    }
+    let mut level = (height / step).floor();
+    // The division can round onto the wrong side of a terrace, so check the level against the multiplication the result comes from.
+    if level * step > height {
+        level -= 1.0;
+    } else if (level + 1.0) * step <= height {
+        level += 1.0;
+    }
+    level * step
+}
+
+// Heights for terrain, as a function of world x and z.
+#[derive(Clone, Debug)]
+pub enum HeightGenerator {
+    // Rolling hills from `amplitude` below to `amplitude` above zero. `frequency` is the number of hills per unit, roughly.
+    Fbm { seed: u64, octaves: u32, frequency: f64, amplitude: f64 },
+    // Mountain ridges from zero up to `amplitude`.
+    Ridged { seed: u64, octaves: u32, frequency: f64, amplitude: f64 },
+    // Another generator's heights, flattened into terraces `step` apart.
+    Terrace { source: Box<HeightGenerator>, step: f64 },
+}
+
+impl HeightGenerator {
+    pub fn height(&self, x: f64, z: f64) -> f64 {
+        match self {
+            HeightGenerator::Fbm { seed, octaves, frequency, amplitude } => amplitude * fbm(*seed, x * frequency, z * frequency, *octaves),
+            HeightGenerator::Ridged { seed, octaves, frequency, amplitude } => amplitude * ridged(*seed, x * frequency, z * frequency, *octaves),
+            HeightGenerator::Terrace { source, step } => terrace(source.height(x, z), *step),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A spread of points, including negative coordinates and points between and on the lattice.
+    fn sample_points() -> impl Iterator<Item = (f64, f64)> {
+        (-20..20).flat_map(|x| (-20..20).map(move |y| (x as f64 * 0.37 + 0.1, y as f64 * 0.53 - 0.2)))
+    }
+
+    #[test]
+    fn same_seed_and_coordinates_give_the_same_value() {
+        for (x, y) in sample_points() {
+            assert_eq!(perlin(7, x, y), perlin(7, x, y));
+            assert_eq!(fbm(7, x, y, 5), fbm(7, x, y, 5));
+            assert_eq!(ridged(7, x, y, 5), ridged(7, x, y, 5));
+        }
+    }
+
+    #[test]
+    fn different_seeds_give_different_values() {
+        let differing = sample_points().filter(|(x, y)| perlin(1, *x, *y) != perlin(2, *x, *y)).count();
+        assert!(differing > sample_points().count() / 2, "only {} points differ", differing);
+        assert!(sample_points().any(|(x, y)| fbm(1, x, y, 4) != fbm(2, x, y, 4)));
+        assert!(sample_points().any(|(x, y)| ridged(1, x, y, 4) != ridged(2, x, y, 4)));
+    }
+
+    #[test]
+    fn perlin_is_zero_on_the_lattice() {
+        for x in -5..5 {
+            for y in -5..5 {
+                assert_eq!(perlin(3, x as f64, y as f64), 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn fbm_and_ridged_stay_in_range() {
+        for seed in 0..4 {
+            for (x, y) in sample_points() {
+                let value = fbm(seed, x, y, 6);
+                assert!((-1.0..=1.0).contains(&value), "fbm({}, {}, {}) = {}", seed, x, y, value);
+                let value = ridged(seed, x, y, 6);
+                assert!((0.0..=1.0).contains(&value), "ridged({}, {}, {}) = {}", seed, x, y, value);
+            }
+        }
+    }
+
+    #[test]
+    fn fbm_and_ridged_vary_on_the_lattice() {
+        for octaves in [1, 4] {
+            let fbm_values: Vec<f64> = (-10..10).flat_map(|x| (-10..10).map(move |y| fbm(9, x as f64, y as f64, octaves))).collect();
+            assert!(fbm_values.iter().all(|value| *value != 0.0), "fbm is 0 at an integer point with {} octaves", octaves);
+            let mean = fbm_values.iter().map(|value| value.abs()).sum::<f64>() / fbm_values.len() as f64;
+            assert!(mean > 0.05, "fbm averages {} at integer points with {} octaves", mean, octaves);
+            assert!((-10..10).all(|x| ridged(9, x as f64, 0.0, octaves) < 1.0));
+        }
+    }
+
+    #[test]
+    fn terrace_is_idempotent() {
+        for step in [0.1, 0.3, 1.0 / 3.0, 2.5] {
+            for (x, _) in sample_points() {
+                let height = x * 3.0;
+                let terraced = terrace(height, step);
+                assert_eq!(terrace(terraced, step), terraced, "terrace({}, {})", height, step);
+                assert!(terraced <= height && height - terraced < step);
+            }
+        }
+    }
+
+    #[test]
+    fn terrace_passes_heights_through_without_a_step() {
+        for height in [-3.7, 0.0, 0.25, 12.0] {
+            assert_eq!(terrace(height, 0.0), height);
+            assert_eq!(terrace(height, -1.0), height);
+        }
+    }
+}
@@ -7,7 +7,7 @@ use combine::{
         token::token,
     }, stream::position::SourcePosition,
 };
-use super::math::number;
+use super::math::signed_number;
 
 #[derive(Debug)]
 pub enum Error {
@@ -134,6 +134,14 @@ impl<'a> SourceListArgument<'a> {
         &self.list.0[self.argument]
     }
     
+    pub fn into_literal(&self) -> Result<&'a str, SourceError> {
+        let list: &'a List = self.list;
+        match &list.0[self.argument].term {
+            ListTerm::Identifier(ident) => Ok(ident),
+            ListTerm::Number(num) => Err(SourceError::unexpected_term(self, "identifier", format!("`{}`", num))),
+            ListTerm::List(_) => Err(SourceError::unexpected_term(self, "identifier", "list".to_string())),
+        }
+    }
     pub fn into_number(&self) -> Result<f64, SourceError> {
         match &self.term().term {
             ListTerm::Identifier(ident) => Err(SourceError::unexpected_term(self, "number", format!("`{}`", ident))),
@@ -172,7 +180,7 @@ where
 {
     position().and(
         choice!(
-            number().map(|num| ListTerm::Number(num)),
+            attempt(signed_number()).map(|num| ListTerm::Number(num)),
             list().map(|list| ListTerm::List(list)),
             identifier().map(|ident| ListTerm::Identifier(ident))
         )
@@ -1,28 +1,223 @@
-use combine::{*, parser::{char::string, combinator::Either}};
+use std::collections::HashMap;
+
+use combine::{*, error::StreamError, parser::char::string, stream::{StreamErrorFor, position::SourcePosition}};
 
 use super::{spaces, newline};
-use super::math::{compare_operator, number, variable, expression, ExpressionTerm, CompareOperator};
+use super::math::{compare_operator, number, signed_number, variable, expression, expression_term, ExpressionTerm, ResolvedTerm, CompareOperator};
 use crate::code::*;
+use crate::color::Color;
+use crate::transform::Vector3f;
+
+// Module names are interned to SymbolIds. A single character name's ID is its code point, so turtle commands can be matched directly.
+// Query modules ?X are given IDs in the private use area so they can't clash with names written directly,
+// and longer names are numbered from the end of the code point range.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SymbolId(u32);
+
+const QUERY_SYMBOL_BASE: u32 = 0xF0000;
+const FIRST_NAMED_SYMBOL: u32 = 0x110000;
+
+impl SymbolId {
+    pub fn from_char(ch: char) -> SymbolId {
+        SymbolId(ch as u32)
+    }
+    pub fn as_char(self) -> Option<char> {
+        char::from_u32(self.0)
+    }
+}
+
+pub fn query_symbol(kind: char) -> SymbolId {
+    SymbolId(QUERY_SYMBOL_BASE + kind as u32)
+}
+
+pub fn query_kind(symbol: SymbolId) -> Option<char> {
+    symbol.0.checked_sub(QUERY_SYMBOL_BASE).and_then(char::from_u32).filter(char::is_ascii_alphabetic)
+}
+
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+    names: Vec<String>,
+    ids: HashMap<String, SymbolId>,
+}
+
+impl SymbolTable {
+    pub fn intern(&mut self, name: &str) -> SymbolId {
+        let mut chars = name.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some(ch), None, _) => return SymbolId::from_char(ch),
+            (Some('?'), Some(kind), None) if kind.is_ascii_alphabetic() => return query_symbol(kind),
+            _ => (),
+        }
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        let id = SymbolId(FIRST_NAMED_SYMBOL + self.names.len() as u32);
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), id);
+        id
+    }
+
+    // Formats a symbol the way it's written in a file.
+    pub fn name(&self, symbol: SymbolId) -> String {
+        if let Some(kind) = query_kind(symbol) {
+            return format!("?{}", kind);
+        }
+        match symbol.0.checked_sub(FIRST_NAMED_SYMBOL) {
+            Some(index) => self.names[index as usize].clone(),
+            None => symbol.as_char().map(String::from).unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    UnknownVariable { unexpected: Variable },
+    MissingParameters { symbol: String, expected: usize, found: usize },
+    Unmatched { symbol: char },
+    NestedPolygon,
+    VertexOutsidePolygon,
+    InvalidTime { time: f64, current: f64 },
+}
+
+// An error in an L-system file found while deriving or interpreting it.
+// `position` is where the offending production or symbol was written, if known, and `step` and `module` locate the module being processed.
+#[derive(Debug)]
+pub struct LSystemError {
+    position: Option<SourcePosition>,
+    step: Option<usize>,
+    module: Option<usize>,
+    error: Error,
+}
+
+impl std::fmt::Display for LSystemError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "L-system error")?;
+        if let Some(position) = self.position {
+            write!(f, " at {}", position)?;
+        }
+        match (self.step, self.module) {
+            (Some(step), Some(module)) => writeln!(f, " (step {}, module {})", step, module)?,
+            (None, Some(module)) => writeln!(f, " (module {})", module)?,
+            _ => writeln!(f)?,
+        }
+        match &self.error {
+            Error::UnknownVariable { unexpected } => write!(f, "Unknown variable \"{}\"", unexpected),
+            Error::MissingParameters { symbol, expected, found } =>
+                write!(f, "Not enough parameters for \"{}\" (need {}, found {})", symbol, expected, found),
+            Error::Unmatched { symbol } => write!(f, "Unmatched \"{}\"", symbol),
+            Error::NestedPolygon => write!(f, "\"{{\" inside a polygon"),
+            Error::VertexOutsidePolygon => write!(f, "\".\" outside of a polygon"),
+            Error::InvalidTime { time, current } => write!(f, "Can't derive to time {} from time {}", time, current),
+        }
+    }
+}
+
+impl std::error::Error for LSystemError {}
+
+impl LSystemError {
+    fn new(position: Option<SourcePosition>, error: Error) -> LSystemError {
+        LSystemError { position, step: None, module: None, error }
+    }
+    pub fn unknown_variable(position: SourcePosition, unexpected: Variable) -> LSystemError {
+        LSystemError::new(Some(position), Error::UnknownVariable { unexpected })
+    }
+    pub fn missing_parameters(position: SourcePosition, symbol: &str, expected: usize, found: usize) -> LSystemError {
+        LSystemError::new(Some(position), Error::MissingParameters { symbol: symbol.to_string(), expected, found })
+    }
+    pub fn unmatched(symbol: char) -> LSystemError {
+        LSystemError::new(None, Error::Unmatched { symbol })
+    }
+    pub fn nested_polygon() -> LSystemError {
+        LSystemError::new(None, Error::NestedPolygon)
+    }
+    pub fn vertex_outside_polygon() -> LSystemError {
+        LSystemError::new(None, Error::VertexOutsidePolygon)
+    }
+    pub fn invalid_time(time: f64, current: f64) -> LSystemError {
+        LSystemError::new(None, Error::InvalidTime { time, current })
+    }
+
+    pub fn at_step(self, step: usize) -> LSystemError {
+        LSystemError { step: Some(step), ..self }
+    }
+    pub fn at_module(self, module: usize) -> LSystemError {
+        LSystemError { module: Some(module), ..self }
+    }
+}
+
+// Lifetime of modules whose symbol has no #lifetime, and which aren't given one where they're written.
+pub const DEFAULT_LIFETIME: f64 = 1.0;
+// Shortest lifetime a module can have. Shorter ones would have derive_to rewrite a module many times over in a single call.
+pub const MIN_LIFETIME: f64 = 0.001;
+
+// Checks a directive's value that must be greater than zero, like a #step.
+fn positive<I: Stream>(value: f64) -> Result<f64, StreamErrorFor<I>> {
+    if value.is_finite() && value > 0.0 {
+        Ok(value)
+    } else {
+        Err(StreamErrorFor::<I>::message_format(format_args!("expected a number greater than 0, not {}", value)))
+    }
+}
+
+// Checks a lifetime written as a number.
+fn valid_lifetime<I: Stream>(lifetime: f64) -> Result<f64, StreamErrorFor<I>> {
+    if lifetime.is_finite() && lifetime >= MIN_LIFETIME {
+        Ok(lifetime)
+    } else {
+        Err(StreamErrorFor::<I>::message_format(format_args!("lifetimes must be at least {}, not {}", MIN_LIFETIME, lifetime)))
+    }
+}
+
+// Checks a lifetime written after an @ if it's a number. Other terms are checked by ResolvedSymbol::push_to as they're evaluated.
+fn valid_lifetime_term<I: Stream>(term: ExpressionTerm) -> Result<ExpressionTerm, StreamErrorFor<I>> {
+    match term {
+        ExpressionTerm::Number(lifetime) => valid_lifetime::<I>(lifetime).map(ExpressionTerm::Number),
+        term => Ok(term),
+    }
+}
 
 #[derive(Debug)]
 pub struct Symbol {
-    pub symbol: char,
+    pub position: SourcePosition,
+    pub name: String,
     pub params: Option<Vec<ExpressionTerm>>,
+    pub lifetime: Option<ExpressionTerm>,
 }
 
-impl Evaluable for Symbol {
-    type Output = crate::lsystem::LSymbol;
-    fn evaluate(&self, scope: VariableScope) -> Self::Output {
-        let params = self.params.as_ref().map(|params| {
-            params.iter().map(|p| p.evaluate(scope)).collect()
-        }).unwrap_or_default();
-        crate::lsystem::LSymbol::new_params(self.symbol, params)
+impl Symbol {
+    // `lifetimes` are the system's #lifetime directives, which give the module its lifetime unless it's written with its own.
+    pub fn resolve(&self, params: &[Variable], constants: VariableScope, lifetimes: &HashMap<String, f64>, symbols: &mut SymbolTable) -> Result<ResolvedSymbol, LSystemError> {
+        let resolve = |term: &ExpressionTerm| term.resolve(params, constants).map_err(|variable| LSystemError::unknown_variable(self.position, variable));
+        Ok(ResolvedSymbol {
+            symbol: symbols.intern(&self.name),
+            params: self.params.iter().flatten().map(resolve).collect::<Result<_, _>>()?,
+            lifetime: self.lifetime.as_ref().map(resolve).transpose()?,
+            default_lifetime: lifetimes.get(&self.name).copied().unwrap_or(DEFAULT_LIFETIME),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct ResolvedSymbol {
+    symbol: SymbolId,
+    params: Vec<ResolvedTerm>,
+    lifetime: Option<ResolvedTerm>,
+    default_lifetime: f64,
+}
+
+impl ResolvedSymbol {
+    // Lifetimes that don't evaluate to a positive number fall back to the default, as derive_to would rewrite the module forever,
+    // and ones shorter than MIN_LIFETIME are lengthened to it.
+    pub fn push_to(&self, params: &[f64], birth: f64, string: &mut crate::lsystem::LString) {
+        let lifetime = self.lifetime.as_ref().map(|term| term.evaluate(params)).filter(|lifetime| lifetime.is_finite() && *lifetime > 0.0);
+        let lifetime = lifetime.map_or(self.default_lifetime, |lifetime| lifetime.max(MIN_LIFETIME));
+        string.push(self.symbol, birth, lifetime, self.params.iter().map(|p| p.evaluate(params)));
     }
 }
 
 #[derive(Debug)]
 pub struct ProductionSymbol {
-    pub symbol: char,
+    pub name: String,
     pub params: Option<Vec<Variable>>,
 }
 
@@ -35,35 +230,26 @@ pub struct Condition {
     right: f64,
 }
 
-impl Evaluable for Condition {
-    type Output = bool;
-    fn evaluate(&self, scope: VariableScope) -> bool {
-        let left = scope.get(&self.left).expect("unknown variable in comparison");
-        match self.op {
-            CompareOperator::Less =>            left <  self.right,
-            CompareOperator::LessEqual =>       left <= self.right,
-            CompareOperator::Equal =>           left == self.right,
-            CompareOperator::NotEqual =>        left != self.right,
-            CompareOperator::GreaterEqual =>    left >= self.right,
-            CompareOperator::Greater =>         left >  self.right,
-        }
+impl Condition {
+    pub fn resolve(&self, params: &[Variable], constants: VariableScope) -> Result<ResolvedCondition, Variable> {
+        Ok(ResolvedCondition {
+            left: ExpressionTerm::Variable(self.left.clone()).resolve(params, constants)?,
+            op: self.op,
+            right: self.right,
+        })
     }
 }
 
-impl Evaluable for Option<Vec<Condition>> {
-    type Output = bool;
-    fn evaluate(&self, scope: VariableScope) -> bool {
-        match self {
-            Some(conds) => {
-                for cond in conds.iter() {
-                    if !cond.evaluate(scope) {
-                        return false;
-                    }
-                }
-                true
-            }
-            None => true,
-        }
+#[derive(Debug)]
+pub struct ResolvedCondition {
+    left: ResolvedTerm,
+    op: CompareOperator,
+    right: f64,
+}
+
+impl ResolvedCondition {
+    pub fn evaluate(&self, params: &[f64]) -> bool {
+        self.op.compare(self.left.evaluate(params), self.right)
     }
 }
 
@@ -75,55 +261,279 @@ pub struct Constant {
 
 #[derive(Debug)]
 pub struct Production {
+    pub position: SourcePosition,
     pub predecessor: ProductionSymbol,
     pub conditions: Option<Vec<Condition>>,
     pub successor: SymbolString,
+    pub weight: Option<f64>,
+}
+
+impl Production {
+    // Resolves the production's variables against its predecessor's parameters, so it can be applied without a VariableScope.
+    pub fn resolve(&self, constants: VariableScope, lifetimes: &HashMap<String, f64>, symbols: &mut SymbolTable) -> Result<ResolvedProduction, LSystemError> {
+        let params = self.predecessor.params.as_deref().unwrap_or_default();
+        Ok(ResolvedProduction {
+            position: self.position,
+            predecessor: self.predecessor.name.clone(),
+            params: params.len(),
+            conditions: self.conditions.iter().flatten()
+                .map(|c| c.resolve(params, constants).map_err(|variable| LSystemError::unknown_variable(self.position, variable)))
+                .collect::<Result<_, _>>()?,
+            successor: self.successor.iter().map(|s| s.resolve(params, constants, lifetimes, symbols)).collect::<Result<_, _>>()?,
+            weight: self.weight,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct ResolvedProduction {
+    pub position: SourcePosition,
+    pub predecessor: String,
+    // Number of parameters the predecessor declares. Modules with fewer can't be rewritten by this production.
+    pub params: usize,
+    pub conditions: Vec<ResolvedCondition>,
+    pub successor: Vec<ResolvedSymbol>,
+    pub weight: Option<f64>,
 }
 
-#[derive(Default, Debug)]
+impl ResolvedProduction {
+    pub fn matches(&self, params: &[f64]) -> Result<bool, LSystemError> {
+        if params.len() < self.params {
+            return Err(LSystemError::missing_parameters(self.position, &self.predecessor, self.params, params.len()));
+        }
+        Ok(self.conditions.iter().all(|condition| condition.evaluate(params)))
+    }
+}
+
+// Directives
+// #axiom A(0) B    The string the system starts from (default: 0).
+// #angle 90        Angle used by turning symbols without parameters. It can be negative, to turn the other way.
+// #step 1          Distance used by moving symbols without parameters. It must be positive, as must #width.
+// #heading 0 1 0   Initial direction the turtle moves in.
+// #up 0 0 -1       Initial up direction of the turtle.
+// #width 0.1       Width of segments drawn by F, until changed by !.
+// #tropism 0 -1 0 0.2  Direction segments bend towards, and how strongly. Segments bend away with a negative strength.
+// #color 1 0.2 0.6 0.1 Palette entry selected by '(1).
+// #double_sided    Emit the back faces of polygons as well.
+// #lifetime A 2    Age at which modules with this symbol are rewritten by LSystem::derive_to (default 1, at least 0.001).
+//                  Their lengths and angles grow from zero over this time.
+// #surface leaf { . G . }  Surface drawn by ~(leaf, scale), modeled by the turtle starting at the origin, facing +z.
+//                  Without a string, the surface is loaded by the host, e.g. from the AssetLibrary.
+//                  The name becomes a constant holding the surface's index. It's an identifier like a module name, or
+//                  quoted to use any other characters, like #surface "tree/leaf" for a mesh the AssetLibrary loaded from a file.
+//
+// A module in the axiom or a successor can be written with its own lifetime after an @, like A(t+1)@2 or A(t+1)@(t*2),
+// overriding #lifetime.
+//
+// A production can end with a weight, like A => F [ + F ] F : 0.3, to make it stochastic.
+// If the first production matching a module has a weight, one of the matching productions with a weight is chosen at random,
+// in proportion to their weights. The random choices are seeded by LSystem::set_seed.
+//
+// Productions are derivation rules unless they follow a section directive:
+// #derivation      Rules applied once per step.
+// #decomposition   Rules applied after each step until no more apply.
+// #interpretation  Rules applied only when the string is turned into geometry.
+#[derive(Debug)]
+pub enum Directive {
+    Section(RuleSection),
+    Axiom(SymbolString),
+    Angle(f64),
+    Step(f64),
+    Heading(Vector3f),
+    Up(Vector3f),
+    Width(f64),
+    Tropism(Vector3f, f64),
+    Color(usize, Color),
+    DoubleSided,
+    Lifetime(String, f64),
+    Surface(Variable, Option<SymbolString>),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum RuleSection {
+    Derivation,
+    Decomposition,
+    Interpretation,
+}
+
+#[derive(Debug)]
+pub struct TurtleSettings {
+    pub heading: Vector3f,
+    pub up: Vector3f,
+    pub angle: f64,
+    pub step: f64,
+    pub width: f64,
+    pub tropism: Vector3f,
+    pub susceptibility: f64,
+    pub palette: Vec<Color>,
+    pub double_sided: bool,
+}
+
+impl Default for TurtleSettings {
+    fn default() -> TurtleSettings {
+        TurtleSettings {
+            heading: Vector3f::unit_y(),
+            up: -Vector3f::unit_z(),
+            angle: 90.0,
+            step: 1.0,
+            width: 0.1,
+            tropism: -Vector3f::unit_y(),
+            susceptibility: 0.0,
+            palette: Vec::new(),
+            double_sided: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Surface {
+    pub name: Variable,
+    pub string: Option<SymbolString>,
+}
+
+#[derive(Debug)]
 pub struct System {
     pub constants: VariableMap,
+    pub axiom: SymbolString,
+    pub turtle: TurtleSettings,
+    pub lifetimes: HashMap<String, f64>,
+    pub surfaces: Vec<Surface>,
     pub productions: Vec<Production>,
+    pub decompositions: Vec<Production>,
+    pub interpretations: Vec<Production>,
+    section: RuleSection,
+}
+
+impl Default for System {
+    fn default() -> System {
+        System {
+            constants: VariableMap::new(),
+            axiom: vec![Symbol { position: SourcePosition::default(), name: "0".to_string(), params: None, lifetime: None }],
+            turtle: TurtleSettings::default(),
+            lifetimes: HashMap::new(),
+            surfaces: Vec::new(),
+            productions: Vec::new(),
+            decompositions: Vec::new(),
+            interpretations: Vec::new(),
+            section: RuleSection::Derivation,
+        }
+    }
 }
 
-impl Extend<Either<Constant, Production>> for System {
-    fn extend<T>(&mut self, iter: T) where T: IntoIterator<Item=Either<Constant, Production>> {
+pub enum Statement {
+    Constant(Constant),
+    Directive(Directive),
+    Production(Production),
+}
+
+impl Extend<Statement> for System {
+    fn extend<T>(&mut self, iter: T) where T: IntoIterator<Item=Statement> {
         for item in iter {
             match item {
-                Either::Left(constant) => { self.constants.insert(constant.left, constant.right); }
-                Either::Right(production) => self.productions.push(production),
+                Statement::Constant(constant) => { self.constants.insert(constant.left, constant.right); }
+                Statement::Directive(Directive::Section(section)) => self.section = section,
+                Statement::Directive(Directive::Axiom(axiom)) => self.axiom = axiom,
+                Statement::Directive(Directive::Angle(angle)) => self.turtle.angle = angle,
+                Statement::Directive(Directive::Step(step)) => self.turtle.step = step,
+                Statement::Directive(Directive::Heading(heading)) => self.turtle.heading = heading,
+                Statement::Directive(Directive::Up(up)) => self.turtle.up = up,
+                Statement::Directive(Directive::Width(width)) => self.turtle.width = width,
+                Statement::Directive(Directive::Tropism(tropism, susceptibility)) => {
+                    self.turtle.tropism = tropism;
+                    self.turtle.susceptibility = susceptibility;
+                }
+                Statement::Directive(Directive::Color(index, color)) => {
+                    if self.turtle.palette.len() <= index {
+                        self.turtle.palette.resize(index + 1, Color::white());
+                    }
+                    self.turtle.palette[index] = color;
+                }
+                Statement::Directive(Directive::DoubleSided) => self.turtle.double_sided = true,
+                Statement::Directive(Directive::Lifetime(symbol, lifetime)) => { self.lifetimes.insert(symbol, lifetime); }
+                Statement::Directive(Directive::Surface(name, string)) => {
+                    self.constants.insert(name.clone(), self.surfaces.len() as f64);
+                    self.surfaces.push(Surface { name, string });
+                }
+                Statement::Production(production) => match self.section {
+                    RuleSection::Derivation => self.productions.push(production),
+                    RuleSection::Decomposition => self.decompositions.push(production),
+                    RuleSection::Interpretation => self.interpretations.push(production),
+                },
             }
         }
     }
 }
 
-pub fn symbol_name<Input>() -> impl Parser<Input, Output = char, PartialState = ()>
+// Module names
+// Modules in a string are separated by whitespace, and each one's name is the first of these that matches:
+// - An identifier: a letter or '_', followed by letters, digits and '_'. So Apex(t) is one module, and FF is one module named "FF", not two F.
+// - A query module: '?' followed by a letter.
+// - Any other single character, except ':' which separates a production from its conditions and weight.
+// Parameters follow the name directly, without whitespace, as does a lifetime.
+fn identifier<'a, I>() -> impl Parser<I, Output = String>
+where
+    I: RangeStream<Token = char, Range = &'a str, Position = SourcePosition>,
+{
+    (
+        satisfy(|ch: char| ch.is_alphabetic() || ch == '_'),
+        many(satisfy(|ch: char| ch.is_alphanumeric() || ch == '_')),
+    ).map(|(first, rest): (char, String)| format!("{}{}", first, rest))
+}
+
+// The name of a constant that a variable can't spell, like a #surface: an identifier, or anything but '"' between quotes.
+fn constant_name<'a, I>() -> impl Parser<I, Output = String>
+where
+    I: RangeStream<Token = char, Range = &'a str, Position = SourcePosition>,
+{
+    choice((
+        between(token('"'), token('"'), many1(satisfy(|ch: char| ch != '"' && ch != '\n'))),
+        identifier(),
+    ))
+}
+
+pub fn symbol_name<'a, I>() -> impl Parser<I, Output = String>
 where
-    Input: Stream<Token = char>,
-    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    I: RangeStream<Token = char, Range = &'a str, Position = SourcePosition>,
 {
-    satisfy(|ch: char| !ch.is_whitespace()).expected("symbol name")
+    let identifier = identifier();
+    let query = attempt((token('?'), satisfy(|ch: char| ch.is_ascii_alphabetic()))).map(|(_, kind)| format!("?{}", kind));
+    let other = satisfy(|ch: char| !ch.is_whitespace() && ch != ':').map(String::from);
+    choice((identifier, query, other)).expected("symbol name")
+}
+
+// A module's parameter is an expression, or a constant_name on its own, which is looked up like a variable. So ~(tree_2, 1)
+// and ~("tree/leaf", 1) can refer to surfaces whose names a variable couldn't spell.
+fn parameter<'a, I>() -> impl Parser<I, Output = ExpressionTerm>
+where
+    I: RangeStream<Token = char, Range = &'a str, Position = SourcePosition>,
+{
+    choice((
+        attempt(constant_name().skip(spaces()).skip(look_ahead(one_of(",)".chars())))).map(ExpressionTerm::Variable),
+        expression(),
+    ))
 }
 
 fn symbol<'a, I>() -> impl Parser<I, Output = Symbol>
 where
-    I: RangeStream<Token = char, Range = &'a str>,
+    I: RangeStream<Token = char, Range = &'a str, Position = SourcePosition>,
 {
-    let params = sep_by1(expression(), token(',').skip(spaces()));
-    symbol_name().and(optional(between(token('('), token(')'), params)))
-        .map(|(symbol, params)| Symbol { symbol, params })
+    let params = sep_by1(parameter(), token(',').skip(spaces()));
+    // A single term, as an expression would run on into the next module.
+    let lifetime = token('@').with(expression_term().and_then(valid_lifetime_term::<I>));
+    (position(), symbol_name(), optional(between(token('('), token(')'), params)), optional(lifetime))
+        .map(|(position, name, params, lifetime)| Symbol { position, name, params, lifetime })
 }
 
 fn symbol_string<'a, I>() -> impl Parser<I, Output = SymbolString>
 where
-    I: RangeStream<Token = char, Range = &'a str>,
+    I: RangeStream<Token = char, Range = &'a str, Position = SourcePosition>,
 {
-    sep_by1(symbol(), skip_many1(one_of(" \t".chars())))
+    sep_by1(symbol(), attempt(skip_many1(one_of(" \t".chars())).skip(look_ahead(symbol_name()))))
 }
 
 fn condition<'a, I>() -> impl Parser<I, Output = Condition>
 where
-    I: RangeStream<Token = char, Range = &'a str>,
+    I: RangeStream<Token = char, Range = &'a str, Position = SourcePosition>,
 {
     (
         variable().skip(spaces()),
@@ -134,39 +544,173 @@ where
 
 pub fn production<'a, I>() -> impl Parser<I, Output = Production>
 where
-    I: RangeStream<Token = char, Range = &'a str>,
+    I: RangeStream<Token = char, Range = &'a str, Position = SourcePosition>,
 {
     let params = sep_by1(variable(), token(',').skip(spaces()));
     let predecessor = symbol_name().and(optional(between(token('('), token(')'), params)))
-        .map(|(symbol, params)| ProductionSymbol { symbol, params });
+        .map(|(name, params)| ProductionSymbol { name, params });
     let conditions = token(':').skip(spaces()).with(sep_by1(condition(), token(',').skip(spaces())));
     (
+        position(),
         predecessor.skip(spaces()),
         optional(conditions.skip(spaces())),
         string("=>").skip(spaces()),
         symbol_string(),
-    ).map(|(predecessor, conditions, _, successor)| Production { predecessor, conditions, successor })
+        optional(attempt(spaces().skip(token(':')).skip(spaces()).with(number()))),
+    ).map(|(position, predecessor, conditions, _, successor, weight)| Production { position, predecessor, conditions, successor, weight })
 }
 
 pub fn constant<'a, I>() -> impl Parser<I, Output = Constant>
 where
-    I: RangeStream<Token = char, Range = &'a str>,
+    I: RangeStream<Token = char, Range = &'a str, Position = SourcePosition>,
 {
     (
         token('#').skip(spaces()),
         variable().skip(spaces()),
         token('=').skip(spaces()),
-        number(),
+        signed_number(),
     ).map(|(_, left, _, right)| Constant { left, right })
 }
 
+fn vector<'a, I>() -> impl Parser<I, Output = Vector3f>
+where
+    I: RangeStream<Token = char, Range = &'a str, Position = SourcePosition>,
+{
+    (
+        signed_number().skip(spaces()),
+        signed_number().skip(spaces()),
+        signed_number(),
+    ).map(|(x, y, z)| Vector3f::new(x as f32, y as f32, z as f32))
+}
+
+// The end of a line, after any trailing spaces, which is left for the caller. Directives end with this, so a value followed by
+// anything else, like #axiom F+F, is an error rather than being cut short.
+fn line_end<'a, I>() -> impl Parser<I, Output = ()>
+where
+    I: RangeStream<Token = char, Range = &'a str, Position = SourcePosition>,
+{
+    attempt(spaces().skip(look_ahead(newline().or(eof())))).expected("end of line")
+}
+
+pub fn directive<'a, I>() -> impl Parser<I, Output = Directive>
+where
+    I: RangeStream<Token = char, Range = &'a str, Position = SourcePosition>,
+{
+    token('#').skip(spaces()).with(choice!(
+        attempt(string("derivation")).map(|_| Directive::Section(RuleSection::Derivation)),
+        attempt(string("decomposition")).map(|_| Directive::Section(RuleSection::Decomposition)),
+        attempt(string("interpretation")).map(|_| Directive::Section(RuleSection::Interpretation)),
+        attempt(string("axiom")).skip(spaces()).with(symbol_string()).map(Directive::Axiom),
+        attempt(string("angle")).skip(spaces()).with(signed_number()).map(Directive::Angle),
+        attempt(string("step")).skip(spaces()).with(signed_number().and_then(positive::<I>)).map(Directive::Step),
+        attempt(string("heading")).skip(spaces()).with(vector()).map(Directive::Heading),
+        attempt(string("up")).skip(spaces()).with(vector()).map(Directive::Up),
+        attempt(string("width")).skip(spaces()).with(signed_number().and_then(positive::<I>)).map(Directive::Width),
+        attempt(string("tropism")).skip(spaces()).with((vector().skip(spaces()), signed_number()))
+            .map(|(tropism, susceptibility)| Directive::Tropism(tropism, susceptibility)),
+        attempt(string("color")).skip(spaces()).with((number().skip(spaces()), vector()))
+            .map(|(index, color)| Directive::Color(index as usize, Color::rgb(color.x, color.y, color.z))),
+        attempt(string("double_sided")).map(|_| Directive::DoubleSided),
+        attempt(string("lifetime")).skip(spaces()).with((symbol_name().skip(spaces()), signed_number().and_then(valid_lifetime::<I>)))
+            .map(|(symbol, lifetime)| Directive::Lifetime(symbol, lifetime))
+    )).skip(line_end())
+}
+
+// Parsed separately from the other directives, since it can end with a symbol string.
+pub fn surface<'a, I>() -> impl Parser<I, Output = Directive>
+where
+    I: RangeStream<Token = char, Range = &'a str, Position = SourcePosition>,
+{
+    let body = optional(attempt(skip_many1(one_of(" \t".chars())).with(symbol_string())));
+    (
+        token('#').skip(spaces()),
+        string("surface").skip(spaces()),
+        constant_name(),
+        body,
+        line_end(),
+    ).map(|(_, _, name, body, _)| Directive::Surface(name, body))
+}
+
 pub fn system<'a, I>() -> impl Parser<I, Output = System>
 where
-    I: RangeStream<Token = char, Range = &'a str>,
+    I: RangeStream<Token = char, Range = &'a str, Position = SourcePosition>,
 {
-    let eol = spaces().with(newline());
-    let line = spaces().with(
-        constant().map(|c| Either::Left(c)).or(production().map(|p| Either::Right(p))));
-    sep_by1(line, skip_many1(eol))
+    // Blank lines and indentation are skipped between statements, and around them at the start and end of the file, whichever
+    // line endings it uses. Anything left over that isn't a statement is an error.
+    let blank_line = || attempt(spaces().with(newline()));
+    let line = choice!(
+        attempt(constant()).map(Statement::Constant),
+        attempt(surface()).map(Statement::Directive),
+        directive().map(Statement::Directive),
+        production().map(Statement::Production)
+    );
+    skip_many(blank_line()).skip(spaces())
+        .with(sep_end_by1(line, skip_many1(blank_line()).skip(spaces())))
+        .skip((spaces(), eof()))
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::{parse_lsystem_file, parse_string};
+
+    const SOURCE: &str = "#axiom A\n#angle 30\nA => F [ + A ] A";
+
+    fn parse(text: &str) -> Result<System, crate::syntax::Error> {
+        parse_string(system(), text)
+    }
+
+    #[test]
+    fn parses_the_example_file_as_saved() {
+        let system = parse_lsystem_file(concat!(env!("CARGO_MANIFEST_DIR"), "/input/system.txt")).unwrap_or_else(|error| panic!("{}", error));
+        assert!(!system.productions.is_empty());
+    }
+
+    #[test]
+    fn allows_blank_lines_trailing_spaces_and_crlf() {
+        for text in [
+            format!("{}\n", SOURCE),
+            format!("{} \t", SOURCE),
+            format!("\n\n{}\n  \n\n", SOURCE),
+            format!("{}\r\n", SOURCE.replace('\n', "  \r\n\r\n")),
+        ] {
+            let system = parse(&text).unwrap_or_else(|error| panic!("{:?}: {}", text, error));
+            assert_eq!((system.axiom.len(), system.turtle.angle, system.productions.len()), (1, 30.0, 1), "{:?}", text);
+            assert_eq!(system.productions[0].successor.len(), 6, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_lifetimes_too_short_to_derive() {
+        for text in ["#lifetime A 0", "#lifetime A 0.0001", "#axiom A@0", "A => B@0.0"] {
+            assert!(parse(text).is_err(), "{:?} parsed", text);
+        }
+        let system = parse("#lifetime A 0.5\nA => B@2 C@(t)").unwrap_or_else(|error| panic!("{}", error));
+        assert_eq!(system.lifetimes.get("A"), Some(&0.5));
+    }
+
+    #[test]
+    fn errors_end_without_a_newline() {
+        let error = LSystemError::missing_parameters(SourcePosition { line: 2, column: 1 }, "A", 2, 1).at_step(3).at_module(4);
+        assert_eq!(error.to_string(), "L-system error at line: 2, column: 1 (step 3, module 4)\nNot enough parameters for \"A\" (need 2, found 1)");
+    }
+
+    #[test]
+    fn parses_negative_values_where_they_make_sense() {
+        let system = parse("#angle -30\n#tropism 0 -1 0 -0.5\n#x = -2").unwrap_or_else(|error| panic!("{}", error));
+        assert_eq!((system.turtle.angle, system.turtle.susceptibility), (-30.0, -0.5));
+        assert_eq!(system.constants.get("x"), Some(&-2.0));
+        for text in ["#step -1", "#step 0", "#width -0.1", "#lifetime A -1"] {
+            let error = parse(text).err().unwrap_or_else(|| panic!("{:?} parsed", text)).to_string();
+            assert!(error.contains("greater than 0") || error.contains("at least"), "{:?} gave {}", text, error);
+        }
+    }
+
+    #[test]
+    fn rejects_unparsed_input() {
+        for text in ["#axiom F+F", "#angle 30 40", "#axiom A\nA => F+F", "#surface leaf . G+G", "#axiom A\n)"] {
+            assert!(parse(text).is_err(), "{:?} parsed", text);
+        }
+    }
+}
@@ -14,7 +14,7 @@ use combine::{
 use super::spaces;
 use crate::code::*;
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub enum Operator {
     Add,
     Subtract,
@@ -22,7 +22,7 @@ pub enum Operator {
     Divide,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub enum CompareOperator {
     Less,
     LessEqual,
@@ -32,6 +32,19 @@ pub enum CompareOperator {
     Greater,
 }
 
+impl CompareOperator {
+    pub fn compare(self, left: f64, right: f64) -> bool {
+        match self {
+            CompareOperator::Less =>            left <  right,
+            CompareOperator::LessEqual =>       left <= right,
+            CompareOperator::Equal =>           left == right,
+            CompareOperator::NotEqual =>        left != right,
+            CompareOperator::GreaterEqual =>    left >= right,
+            CompareOperator::Greater =>         left >  right,
+        }
+    }
+}
+
 impl FromStr for CompareOperator {
     type Err = String;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -54,14 +67,22 @@ pub enum ExpressionTerm {
     Expression(Box<Expression>),
 }
 
-impl Evaluable for ExpressionTerm {
-    type Output = f64;
-    fn evaluate(&self, scope: VariableScope) -> f64 {
-        match self {
-            ExpressionTerm::Variable(var) => scope.get(var).expect("unknown variable in expression"),
-            ExpressionTerm::Number(value) => *value,
-            ExpressionTerm::Expression(expr) => expr.evaluate(scope),
-        }
+impl ExpressionTerm {
+    // Resolves variables ahead of time: names in `params` become parameter slots, and anything else is looked up in `constants`.
+    // Returns the name of the first variable that is neither.
+    pub fn resolve(&self, params: &[Variable], constants: VariableScope) -> Result<ResolvedTerm, Variable> {
+        Ok(match self {
+            ExpressionTerm::Variable(var) => match params.iter().rposition(|param| param == var) {
+                Some(index) => ResolvedTerm::Parameter(index),
+                None => ResolvedTerm::Number(constants.get(var).ok_or_else(|| var.clone())?),
+            },
+            ExpressionTerm::Number(value) => ResolvedTerm::Number(*value),
+            ExpressionTerm::Expression(expr) => ResolvedTerm::Expression(Box::new(ResolvedExpression {
+                left: expr.left.resolve(params, constants)?,
+                op: expr.op,
+                right: expr.right.resolve(params, constants)?,
+            })),
+        })
     }
 }
 
@@ -72,12 +93,9 @@ pub struct Expression {
     right: ExpressionTerm,
 }
 
-impl Evaluable for Expression {
-    type Output = f64;
-    fn evaluate(&self, scope: VariableScope) -> f64 {
-        let left = self.left.evaluate(scope);
-        let right = self.right.evaluate(scope);
-        match self.op {
+impl Operator {
+    fn apply(self, left: f64, right: f64) -> f64 {
+        match self {
             Operator::Add => left + right,
             Operator::Subtract => left - right,
             Operator::Multiply => left * right,
@@ -86,6 +104,31 @@ impl Evaluable for Expression {
     }
 }
 
+#[derive(Debug)]
+pub enum ResolvedTerm {
+    Parameter(usize),
+    Number(f64),
+    Expression(Box<ResolvedExpression>),
+}
+
+impl ResolvedTerm {
+    // Productions check that a module has all the parameters they use before evaluating any of their terms.
+    pub fn evaluate(&self, params: &[f64]) -> f64 {
+        match self {
+            ResolvedTerm::Parameter(index) => params[*index],
+            ResolvedTerm::Number(value) => *value,
+            ResolvedTerm::Expression(expr) => expr.op.apply(expr.left.evaluate(params), expr.right.evaluate(params)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ResolvedExpression {
+    left: ResolvedTerm,
+    op: Operator,
+    right: ResolvedTerm,
+}
+
 pub fn number<'a, I>() -> impl Parser<I, Output = f64>
 where
     I: RangeStream<Token = char, Range = &'a str>,
@@ -96,6 +139,17 @@ where
     )))
 }
 
+pub fn signed_number<'a, I>() -> impl Parser<I, Output = f64>
+where
+    I: RangeStream<Token = char, Range = &'a str>,
+{
+    from_str(recognize((
+        optional(token('-')),
+        skip_many1(digit()),
+        optional((token('.'), skip_many(digit()))),
+    )))
+}
+
 pub fn variable<I>() -> impl Parser<I, Output = Variable>
 where
     I: Stream<Token = char>,
@@ -51,6 +51,11 @@ pub fn parse_code_file<P: AsRef<std::path::Path>>(path: P, scope: VariableScope)
     code.iter().map(|item| item.evaluate(scope).map_err(Error::Evaluate)).collect()
 }
 
+pub fn parse_lsystem_file<P: AsRef<std::path::Path>>(path: P) -> Result<lsystem::System, Error> {
+    let text = std::fs::read_to_string(path).map_err(Error::Io)?;
+    parse_string(lsystem::system(), &text)
+}
+
 pub fn spaces<Input>() -> impl Parser<Input, Output = ()>
 where
     Input: Stream<Token = char>,
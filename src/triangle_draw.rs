@@ -24,20 +24,48 @@ use vulkano::pipeline::viewport::Viewport;
 use vulkano::pipeline::{PipelineBindPoint, GraphicsPipeline};
 use vulkano::render_pass::Subpass;
 
+use crate::bounds::{Aabb, BoundingSphere};
 use crate::color::Color;
-use crate::transform::Transform;
+use crate::mesh_generation::{MeshError, generate_tangents, validate_mesh};
+use crate::transform::{Point3f, Transform};
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct TriangleMesh {
     pub positions: Vec<[f32; 3]>,
     pub normals: Vec<[f32; 3]>,
+    // Multiplied with the material color. Vertices without a color are white.
+    pub colors: Vec<[f32; 4]>,
+    // Texture coordinates. Either empty, or one per vertex like colors.
+    pub uvs: Vec<[f32; 2]>,
+    // Directions of increasing u, with w the sign of the bitangent: cross(normal, tangent) * w points along increasing v.
+    // Either empty, or one per vertex. Meshes with UVs but no tangents get them from generate_tangents when loaded.
+    pub tangents: Vec<[f32; 4]>,
     pub indices: Vec<u32>,
 }
 
+// Meshes with UVs are uploaded with their UVs and tangents, and drawn with the pipeline that takes them.
+#[derive(Clone)]
+enum VertexBuffer {
+    Plain(Arc<CpuAccessibleBuffer<[Vertex]>>),
+    Textured(Arc<CpuAccessibleBuffer<[TexturedVertex]>>),
+}
+
 #[derive(Clone)]
 pub struct TriangleMeshHandle {
-    vertex_buffer: Arc<CpuAccessibleBuffer<[Vertex]>>,
+    vertex_buffer: VertexBuffer,
     index_buffer: Arc<CpuAccessibleBuffer<[u32]>>,
+    // In the mesh's own coordinates.
+    aabb: Aabb,
+    bounding_sphere: BoundingSphere,
+}
+
+impl TriangleMeshHandle {
+    pub fn aabb(&self) -> Aabb {
+        self.aabb
+    }
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        self.bounding_sphere
+    }
 }
 
 #[derive(Clone)]
@@ -48,12 +76,29 @@ pub struct TriangleMaterialHandle {
 #[derive(Clone)]
 pub struct TriangleDrawable {
     pub meshes: Vec<(TriangleMaterialHandle, TriangleMeshHandle)>,
+    // Simplified versions of `meshes`, in order of increasing distance.
+    pub lods: Vec<Lod>,
     pub transform: Transform,
 }
 
+impl TriangleDrawable {
+    // The meshes to draw when the camera is `distance` away.
+    pub fn meshes_at(&self, distance: f32) -> &[(TriangleMaterialHandle, TriangleMeshHandle)] {
+        self.lods.iter().rev().find(|lod| distance >= lod.distance).map_or(&self.meshes, |lod| &lod.meshes)
+    }
+}
+
+// A level of detail, drawn instead of a drawable's meshes when the camera is at least `distance` away.
+#[derive(Clone)]
+pub struct Lod {
+    pub distance: f32,
+    pub meshes: Vec<(TriangleMaterialHandle, TriangleMeshHandle)>,
+}
+
 pub struct TriangleDrawSystem {
     gfx_queue: Arc<Queue>,
     pipeline: Arc<GraphicsPipeline>,
+    textured_pipeline: Arc<GraphicsPipeline>,
 }
 
 pub type CameraData = vs::ty::CameraData;
@@ -75,6 +120,25 @@ impl TriangleDrawSystem {
                     .viewports_dynamic_scissors_irrelevant(1)
                     .fragment_shader(fs.main_entry_point(), ())
                     .depth_stencil_simple_depth()
+                    .render_pass(subpass.clone())
+                    .build(gfx_queue.device().clone())
+                    .unwrap(),
+            ) as Arc<_>
+        };
+        let textured_pipeline = {
+            let vs = textured_vs::Shader::load(gfx_queue.device().clone())
+                .expect("failed to create shader module");
+            let fs = fs::Shader::load(gfx_queue.device().clone())
+                .expect("failed to create shader module");
+
+            Arc::new(
+            GraphicsPipeline::start()
+                    .vertex_input_single_buffer::<TexturedVertex>()
+                    .vertex_shader(vs.main_entry_point(), ())
+                    .triangle_list()
+                    .viewports_dynamic_scissors_irrelevant(1)
+                    .fragment_shader(fs.main_entry_point(), ())
+                    .depth_stencil_simple_depth()
                     .render_pass(subpass)
                     .build(gfx_queue.device().clone())
                     .unwrap(),
@@ -84,6 +148,7 @@ impl TriangleDrawSystem {
         TriangleDrawSystem {
             gfx_queue: gfx_queue,
             pipeline: pipeline,
+            textured_pipeline,
         }
     }
 
@@ -91,25 +156,53 @@ impl TriangleDrawSystem {
         self.gfx_queue.device().clone()
     }
 
-    pub fn load_mesh(&self, mesh: TriangleMesh) -> TriangleMeshHandle {
-        let vertex_buffer = CpuAccessibleBuffer::from_iter(
-            self.gfx_queue.device().clone(),
-            BufferUsage::all(),
-            false,
-            mesh.positions.iter().zip(mesh.normals.iter()).map(|(position, normal)| {
-                Vertex {
-                    position: *position,
-                    normal: *normal,
-                }
-            }),
-        ).expect("failed to create vertex buffer");
+    // Fails without uploading anything if the mesh doesn't pass validate_mesh. The vertex layout matches the mesh: meshes with
+    // UVs are uploaded with them and their tangents, which are generated if the mesh has none.
+    pub fn load_mesh(&self, mut mesh: TriangleMesh) -> Result<TriangleMeshHandle, MeshError> {
+        validate_mesh(&mesh)?;
+        if !mesh.uvs.is_empty() && mesh.tangents.is_empty() {
+            generate_tangents(&mut mesh);
+        }
+        let points: Vec<Point3f> = mesh.positions.iter().map(|position| Point3f::from(*position)).collect();
+        let aabb = Aabb::from_points(points.iter().copied());
+        let bounding_sphere = BoundingSphere::from_points(&points);
+        let color = |index: usize| mesh.colors.get(index).copied().unwrap_or_else(|| Color::white().into());
+        let vertex_buffer = if mesh.uvs.is_empty() {
+            VertexBuffer::Plain(CpuAccessibleBuffer::from_iter(
+                self.gfx_queue.device().clone(),
+                BufferUsage::all(),
+                false,
+                mesh.positions.iter().zip(mesh.normals.iter()).enumerate().map(|(index, (position, normal))| {
+                    Vertex {
+                        position: *position,
+                        normal: *normal,
+                        color: color(index),
+                    }
+                }),
+            ).expect("failed to create vertex buffer"))
+        } else {
+            VertexBuffer::Textured(CpuAccessibleBuffer::from_iter(
+                self.gfx_queue.device().clone(),
+                BufferUsage::all(),
+                false,
+                mesh.positions.iter().zip(mesh.normals.iter()).enumerate().map(|(index, (position, normal))| {
+                    TexturedVertex {
+                        position: *position,
+                        normal: *normal,
+                        color: color(index),
+                        uv: mesh.uvs[index],
+                        tangent: mesh.tangents[index],
+                    }
+                }),
+            ).expect("failed to create vertex buffer"))
+        };
         let index_buffer = CpuAccessibleBuffer::from_iter(
             self.gfx_queue.device().clone(),
             BufferUsage::all(),
             false,
             mesh.indices.iter().cloned(),
         ).expect("failed to create index buffer");
-        TriangleMeshHandle { vertex_buffer, index_buffer }
+        Ok(TriangleMeshHandle { vertex_buffer, index_buffer, aabb, bounding_sphere })
     }
     pub fn load_material(&self, color: Color) -> TriangleMaterialHandle {
         let data_buffer = CpuAccessibleBuffer::from_data(
@@ -145,36 +238,67 @@ impl TriangleDrawSystem {
             )
             .bind_pipeline_graphics(self.pipeline.clone());
         TriangleDraw {
-            pipeline_layout: self.pipeline.layout().clone(),
+            pipeline: self.pipeline.clone(),
+            textured_pipeline: self.textured_pipeline.clone(),
+            textured: false,
+            camera: None,
             builder,
         }
     }
 }
 
 pub struct TriangleDraw {
-    pipeline_layout: Arc<PipelineLayout>,
+    pipeline: Arc<GraphicsPipeline>,
+    textured_pipeline: Arc<GraphicsPipeline>,
+    // Whether the textured pipeline is bound. Pipelines are only switched when the next mesh's vertex layout differs.
+    textured: bool,
+    camera: Option<Arc<PersistentDescriptorSet>>,
     builder: AutoCommandBufferBuilder<SecondaryAutoCommandBuffer>,
 }
 
 impl TriangleDraw {
+    fn pipeline_layout(&self) -> Arc<PipelineLayout> {
+        let pipeline = if self.textured { &self.textured_pipeline } else { &self.pipeline };
+        pipeline.layout().clone()
+    }
     pub fn set_camera(&mut self, data_buffer: Arc<dyn BufferAccess + 'static>) {
-        let layout = self.pipeline_layout.descriptor_set_layouts().get(0).unwrap();
+        let layout = self.pipeline.layout().descriptor_set_layouts().get(0).unwrap();
         let mut set_builder = PersistentDescriptorSet::start(layout.clone());
         set_builder.add_buffer(data_buffer).unwrap();
-        let set = set_builder.build().unwrap();
-        self.builder.bind_descriptor_sets(PipelineBindPoint::Graphics, self.pipeline_layout.clone(), 0, set);
+        let set = Arc::new(set_builder.build().unwrap());
+        let pipeline_layout = self.pipeline_layout();
+        self.builder.bind_descriptor_sets(PipelineBindPoint::Graphics, pipeline_layout, 0, set.clone());
+        self.camera = Some(set);
     }
-    pub fn draw(&mut self, drawable: &TriangleDrawable) {
+    // Binds the pipeline matching a vertex layout, along with the camera, if it isn't already.
+    fn bind_pipeline(&mut self, textured: bool) {
+        if textured == self.textured {
+            return;
+        }
+        self.textured = textured;
+        let pipeline = if textured { self.textured_pipeline.clone() } else { self.pipeline.clone() };
+        self.builder.bind_pipeline_graphics(pipeline);
+        if let Some(camera) = self.camera.clone() {
+            let pipeline_layout = self.pipeline_layout();
+            self.builder.bind_descriptor_sets(PipelineBindPoint::Graphics, pipeline_layout, 0, camera);
+        }
+    }
+    pub fn draw(&mut self, transform: Transform, meshes: &[(TriangleMaterialHandle, TriangleMeshHandle)]) {
         let push_constants = vs::ty::PushConstants {
-            world: Matrix4::from(drawable.transform).into(),
+            world: Matrix4::from(transform).into(),
         };
-        for (material, mesh) in drawable.meshes.iter() {
+        for (material, mesh) in meshes.iter() {
+            self.bind_pipeline(matches!(mesh.vertex_buffer, VertexBuffer::Textured(_)));
+            let pipeline_layout = self.pipeline_layout();
+            match &mesh.vertex_buffer {
+                VertexBuffer::Plain(buffer) => self.builder.bind_vertex_buffers(0, buffer.clone()),
+                VertexBuffer::Textured(buffer) => self.builder.bind_vertex_buffers(0, buffer.clone()),
+            };
             let index_count = mesh.index_buffer.len() as u32;
             self.builder
-                .bind_descriptor_sets(PipelineBindPoint::Graphics, self.pipeline_layout.clone(), 1, material.descriptor_set.clone())
-                .bind_vertex_buffers(0, mesh.vertex_buffer.clone())
+                .bind_descriptor_sets(PipelineBindPoint::Graphics, pipeline_layout.clone(), 1, material.descriptor_set.clone())
                 .bind_index_buffer(mesh.index_buffer.clone())
-                .push_constants(self.pipeline_layout.clone(), 0, push_constants)
+                .push_constants(pipeline_layout, 0, push_constants)
                 .draw_indexed(index_count, 1, 0, 0, 0).unwrap();
         }
     }
@@ -187,8 +311,19 @@ impl TriangleDraw {
 struct Vertex {
     position: [f32; 3],
     normal: [f32; 3],
+    color: [f32; 4],
+}
+vulkano::impl_vertex!(Vertex, position, normal, color);
+
+#[derive(Default, Debug, Clone)]
+struct TexturedVertex {
+    position: [f32; 3],
+    normal: [f32; 3],
+    color: [f32; 4],
+    uv: [f32; 2],
+    tangent: [f32; 4],
 }
-vulkano::impl_vertex!(Vertex, position, normal);
+vulkano::impl_vertex!(TexturedVertex, position, normal, color, uv, tangent);
 
 #[derive(Default, Debug, Clone, Copy)]
 struct Material {
@@ -203,7 +338,9 @@ mod vs {
 
 layout(location = 0) in vec3 position;
 layout(location = 1) in vec3 normal;
+layout(location = 2) in vec4 color;
 layout(location = 0) out vec3 v_normal;
+layout(location = 1) out vec4 v_color;
 
 layout(push_constant) uniform PushConstants {
 	mat4 world;
@@ -216,6 +353,7 @@ layout(set = 0, binding = 0) uniform CameraData {
 
 void main() {
     v_normal = transpose(inverse(mat3(object.world))) * normal;
+    v_color = color;
     gl_Position = camera.proj * camera.view * object.world * vec4(position, 1.0);
 }"
     }
@@ -228,6 +366,7 @@ mod fs {
 #version 450
 
 layout(location = 0) in vec3 v_normal;
+layout(location = 1) in vec4 v_color;
 layout(location = 0) out vec4 f_color;
 layout(location = 1) out vec3 f_normal;
 
@@ -236,8 +375,40 @@ layout(set = 1, binding = 0) uniform MaterialData {
 } material;
 
 void main() {
-    f_color = material.color;
+    f_color = material.color * v_color;
     f_normal = normalize(v_normal);
 }"
     }
-}
\ No newline at end of file
+}
+// The same as vs, but taking the TexturedVertex layout. Its UVs and tangents aren't read until materials have textures, so textured
+// meshes are drawn with fs too.
+mod textured_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec4 color;
+layout(location = 3) in vec2 uv;
+layout(location = 4) in vec4 tangent;
+layout(location = 0) out vec3 v_normal;
+layout(location = 1) out vec4 v_color;
+
+layout(push_constant) uniform PushConstants {
+	mat4 world;
+} object;
+
+layout(set = 0, binding = 0) uniform CameraData {
+    mat4 view;
+    mat4 proj;
+} camera;
+
+void main() {
+    v_normal = transpose(inverse(mat3(object.world))) * normal;
+    v_color = color;
+    gl_Position = camera.proj * camera.view * object.world * vec4(position, 1.0);
+}"
+    }
+}
@@ -3,7 +3,7 @@ use std::sync::Arc;
 use cgmath::{EuclideanSpace, Matrix4, Point3, Rad, SquareMatrix, Transform as TransformMath};
 use vulkano::{buffer::{BufferAccess, BufferUsage, CpuBufferPool}, device::Device};
 
-use crate::{transform::Transform, triangle_draw::CameraData};
+use crate::{bounds::Frustum, transform::Transform, triangle_draw::CameraData};
 
 pub struct CameraSystem {
     viewport_dimensions: [u32; 2],
@@ -48,6 +48,9 @@ impl CameraSystem {
     pub fn world_to_framebuffer(&self) -> Matrix4<f32> {
         self.proj * self.view
     }
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_matrix(self.world_to_framebuffer())
+    }
     pub fn frame_data(&self) -> Arc<dyn BufferAccess + 'static> {
         let uniform_data = CameraData {
             view: self.view.into(),
@@ -1,6 +1,6 @@
 use cgmath::{Deg, Euler, Quaternion};
 
-use crate::{transform::QuaternionExtensions, world::input::FlyingControls};
+use crate::{transform::{Transform, QuaternionExtensions}, world::input::FlyingControls};
 
 use super::{DrawableId, DrawableComponentList, new_component_list_type};
 
@@ -28,11 +28,33 @@ impl AvatarComponent {
     pub fn parent(&self) -> DrawableId {
         self.parent
     }
+
+    pub fn rotate(&mut self, transform: &mut Transform, angles: Euler<Deg<f32>>) {
+        match &mut self.controls {
+            AvatarControls::Flying(_, camera_angle) => {
+                camera_angle.x += angles.x;
+                camera_angle.y += angles.y;
+                camera_angle.z += angles.z;
+                transform.rot = Quaternion::from_euler_yxz(*camera_angle);
+            }
+        }
+    }
+    pub fn set_rotation(&mut self, transform: &mut Transform, angles: Euler<Deg<f32>>) {
+        match &mut self.controls {
+            AvatarControls::Flying(_, camera_angle) => {
+                *camera_angle = angles;
+                transform.rot = Quaternion::from_euler_yxz(*camera_angle);
+            }
+        }
+    }
 }
 
 new_component_list_type!(AvatarComponentList, AvatarId, AvatarComponent);
 
 impl AvatarComponentList {
+    pub fn find_by_parent_mut(&mut self, parent: DrawableId) -> Option<&mut AvatarComponent> {
+        self.0.values_mut().find(|component| component.parent == parent)
+    }
     pub fn update(&mut self, drawables: &mut DrawableComponentList, delta_time: f64) {
         for component in self.0.values_mut() {
             let transform = &mut drawables.get_mut(component.parent).unwrap().transform;
@@ -1,9 +1,20 @@
 pub mod avatar;
+pub mod motion;
+pub mod plant;
 pub mod terrain;
+pub mod volume;
 
-use crate::triangle_draw::{TriangleDraw, TriangleDrawable, TriangleDrawSystem};
+use cgmath::{EuclideanSpace, MetricSpace};
+
+use crate::bounds::Frustum;
+use crate::mesh_generation::{MeshError, lod_chain};
+use crate::transform::Point3f;
+use crate::triangle_draw::{Lod, TriangleDraw, TriangleDrawable, TriangleDrawSystem, TriangleMaterialHandle, TriangleMesh, TriangleMeshHandle};
 use avatar::AvatarComponentList;
+use motion::MotionComponentList;
+use plant::PlantComponentList;
 use terrain::TerrainComponentList;
+use volume::VolumeComponentList;
 
 use super::Globals;
 
@@ -26,6 +37,9 @@ macro_rules! new_component_list_type {
             pub fn get_mut(&mut self, id: $id) -> Option<&mut $comp> {
                 self.0.get_mut(id)
             }
+            pub fn iter(&self) -> impl Iterator<Item = &$comp> {
+                self.0.values()
+            }
         }
     };
 }
@@ -37,17 +51,46 @@ new_component_list_type!(DrawableComponentList, DrawableId, TriangleDrawable);
 pub struct ComponentSystem {
     pub drawables: DrawableComponentList,
     pub avatars: AvatarComponentList,
+    pub motions: MotionComponentList,
     pub terrain: TerrainComponentList,
+    pub volumes: VolumeComponentList,
+    pub plants: PlantComponentList,
 }
 
 impl ComponentSystem {
     pub fn update(&mut self, globals: &Globals, draw_system: &TriangleDrawSystem, delta_time: f64) {
+        self.motions.update(&mut self.drawables, &mut self.avatars, delta_time);
         self.avatars.update(&mut self.drawables, delta_time);
         self.terrain.update(globals, draw_system, &mut self.drawables);
+        self.volumes.update(globals, draw_system, &mut self.drawables);
+        self.plants.update(globals, draw_system, &mut self.drawables, &self.terrain, delta_time);
     }
-    pub fn render(&self, renderer: &mut TriangleDraw) {
+    // Skips drawables whose meshes are all outside the frustum. The bounding sphere is the cheaper test, and the box catches long,
+    // thin meshes like terrain that a sphere fits loosely.
+    pub fn render(&self, renderer: &mut TriangleDraw, camera_position: Point3f, frustum: &Frustum) {
         for entity in self.drawables.0.values() {
-            renderer.draw(entity);
+            let distance = camera_position.distance(Point3f::from_vec(entity.transform.disp));
+            let meshes = entity.meshes_at(distance);
+            let visible = |mesh: &TriangleMeshHandle| frustum.intersects_sphere(&mesh.bounding_sphere().transform(entity.transform))
+                && frustum.intersects_aabb(&mesh.aabb().transform(entity.transform));
+            if meshes.iter().any(|(_, mesh)| visible(mesh)) {
+                renderer.draw(entity.transform, meshes);
+            }
         }
     }
 }
+
+// Camera distances at which generated meshes switch to each level of their LOD chain. Each level has half the triangles of the one before.
+const LOD_DISTANCES: [f32; 3] = [16.0, 32.0, 64.0];
+// Meshes smaller than this are always drawn in full.
+const MIN_LOD_TRIANGLES: usize = 256;
+
+// Simplifies and loads a LOD chain for a generated mesh drawn with a single material.
+fn load_lods(draw_system: &TriangleDrawSystem, material: &TriangleMaterialHandle, mesh: &TriangleMesh) -> Result<Vec<Lod>, MeshError> {
+    if mesh.indices.len() / 3 < MIN_LOD_TRIANGLES {
+        return Ok(Vec::new());
+    }
+    lod_chain(mesh, LOD_DISTANCES.len()).into_iter().zip(LOD_DISTANCES).map(|(lod, distance)| {
+        Ok(Lod { distance, meshes: vec![(material.clone(), draw_system.load_mesh(lod)?)] })
+    }).collect()
+}
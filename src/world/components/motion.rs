@@ -0,0 +1,67 @@
+use cgmath::{Deg, Euler, One};
+
+use crate::transform::{Quaternion, QuaternionExtensions, Vector3f};
+
+use super::{DrawableId, DrawableComponentList, avatar::AvatarComponentList, new_component_list_type};
+
+#[derive(Clone, Copy, Debug)]
+pub enum Motion {
+    Translate(Vector3f),
+    Rotate(Euler<Deg<f32>>),
+    Scale(f32),
+}
+
+impl Motion {
+    pub fn apply(&self, entity: DrawableId, drawables: &mut DrawableComponentList, avatars: &mut AvatarComponentList) {
+        self.apply_fraction(entity, drawables, avatars, 1.0);
+    }
+    // Applies the given fraction of this motion. Applying fractions that sum to 1 is the same as applying the whole motion.
+    fn apply_fraction(&self, entity: DrawableId, drawables: &mut DrawableComponentList, avatars: &mut AvatarComponentList, fraction: f32) {
+        let transform = match drawables.get_mut(entity) {
+            Some(drawable) => &mut drawable.transform,
+            None => return,
+        };
+        match *self {
+            Motion::Translate(offset) => transform.disp += offset * fraction,
+            Motion::Rotate(angles) => {
+                if let Some(avatar) = avatars.find_by_parent_mut(entity) {
+                    // The avatar recomputes its rotation from its camera angles every frame, so rotate those instead.
+                    avatar.rotate(transform, Euler::new(angles.x * fraction, angles.y * fraction, angles.z * fraction));
+                } else {
+                    let rotation = cgmath::Quaternion::from_euler_yxz(angles);
+                    transform.rot = transform.rot * Quaternion::one().slerp(rotation, fraction);
+                }
+            }
+            Motion::Scale(factor) => transform.scale *= factor.powf(fraction),
+        }
+    }
+}
+
+pub struct MotionComponent {
+    entity: DrawableId,
+    motion: Motion,
+    duration: f64,
+    elapsed: f64,
+}
+
+impl MotionComponent {
+    pub fn new(entity: DrawableId, motion: Motion, duration: f64) -> MotionComponent {
+        MotionComponent { entity, motion, duration, elapsed: 0.0 }
+    }
+}
+
+new_component_list_type!(MotionComponentList, MotionId, MotionComponent);
+
+impl MotionComponentList {
+    pub fn update(&mut self, drawables: &mut DrawableComponentList, avatars: &mut AvatarComponentList, delta_time: f64) {
+        self.0.retain(|_, component| {
+            if drawables.get(component.entity).is_none() {
+                return false;
+            }
+            let step = delta_time.min(component.duration - component.elapsed);
+            component.elapsed += step;
+            component.motion.apply_fraction(component.entity, drawables, avatars, (step / component.duration) as f32);
+            component.elapsed < component.duration
+        });
+    }
+}
@@ -0,0 +1,117 @@
+use cgmath::Transform as _;
+
+use crate::{lsystem::{Environment, LSystem}, syntax::lsystem::LSystemError, transform::{Point3f, Transform, Vector3f}, triangle_draw::{TriangleDrawSystem, TriangleMesh}};
+
+use super::{Globals, DrawableId, DrawableComponentList, load_lods, new_component_list_type, terrain::TerrainComponentList};
+
+// Seconds of growth between rebuilds of a plant's LOD chain.
+const LOD_INTERVAL: f64 = 1.0;
+
+// A drawable whose mesh is a timed L-system, growing as the world runs.
+pub struct PlantComponent {
+    parent: DrawableId,
+    lsystem: LSystem,
+    dirty: bool,
+    age: f64,
+    // Age at which the mesh was last made.
+    mesh_age: f64,
+    // Age at which the LOD chain was last rebuilt. Simplifying is too slow to do every frame, and distant plants don't need to grow smoothly.
+    lod_age: Option<f64>,
+}
+
+impl PlantComponent {
+    pub fn new(parent: DrawableId, mut lsystem: LSystem) -> Result<PlantComponent, LSystemError> {
+        lsystem.start()?;
+        Ok(PlantComponent { parent, lsystem, dirty: true, age: 0.0, mesh_age: 0.0, lod_age: None })
+    }
+    // Returns the plant's new mesh, or None if it would look the same as the last one. A plant that has stopped growing
+    // still gets one more mesh to rebuild its LOD chain from, if that was last rebuilt before its final growth.
+    fn grow(&mut self, environment: &mut PlantEnvironment, delta_time: f64) -> Result<Option<TriangleMesh>, LSystemError> {
+        let growing = self.lsystem.is_growing();
+        self.age += delta_time;
+        // Query modules are only read by productions, so they don't need answering until a module is due to be rewritten.
+        if self.lsystem.next_expiry() <= self.age {
+            self.lsystem.query(environment)?;
+        }
+        let rewritten = self.lsystem.derive_to(self.age)?;
+        let stale_lods = self.lod_age.is_some_and(|lod_age| lod_age < self.mesh_age && self.age - lod_age >= LOD_INTERVAL);
+        if !(self.dirty || growing || rewritten || stale_lods) {
+            return Ok(None);
+        }
+        self.dirty = false;
+        self.mesh_age = self.age;
+        self.lsystem.make_mesh().map(Some)
+    }
+    pub fn parent(&self) -> DrawableId {
+        self.parent
+    }
+    pub fn lsystem(&self) -> &LSystem {
+        &self.lsystem
+    }
+}
+
+// Answers a plant's ?E(h) modules with the height of the module above the terrain, or infinity if there's no terrain below it.
+struct PlantEnvironment<'a> {
+    transform: Transform,
+    terrain: &'a TerrainComponentList,
+    drawables: &'a DrawableComponentList,
+}
+
+impl<'a> Environment for PlantEnvironment<'a> {
+    fn query(&mut self, position: Point3f, _heading: Vector3f, params: &mut [f64]) {
+        let position = self.transform.transform_point(position);
+        if let Some(param) = params.first_mut() {
+            *param = match self.terrain.height_at(self.drawables, position) {
+                Some(height) => (position.y - height) as f64,
+                None => f64::INFINITY,
+            };
+        }
+    }
+}
+
+new_component_list_type!(PlantComponentList, PlantId, PlantComponent);
+
+impl PlantComponentList {
+    // A plant whose L-system fails, or makes a mesh that can't be loaded, stops growing and keeps the last mesh it made.
+    pub fn update(&mut self, globals: &Globals, draw_system: &TriangleDrawSystem, drawables: &mut DrawableComponentList, terrain: &TerrainComponentList, delta_time: f64) {
+        self.0.retain(|_, component| {
+            let transform = drawables.get(component.parent).unwrap().transform;
+            let mesh = match component.grow(&mut PlantEnvironment { transform, terrain, drawables }, delta_time) {
+                Ok(Some(mesh)) => mesh,
+                Ok(None) => return true,
+                Err(error) => {
+                    println!("{}", error);
+                    return false;
+                }
+            };
+            // An empty mesh has no LODs to rebuild, so it counts as rebuilding them, rather than asking for the mesh again.
+            if mesh.indices.is_empty() {
+                component.lod_age = Some(component.age);
+            } else {
+                let rebuild_lods = component.lod_age.map_or(true, |lod_age| component.age - lod_age >= LOD_INTERVAL);
+                let lods = match rebuild_lods {
+                    true => load_lods(draw_system, &globals.default_plant_material, &mesh).map(Some),
+                    false => Ok(None),
+                };
+                let (mesh, lods) = match lods.and_then(|lods| Ok((draw_system.load_mesh(mesh)?, lods))) {
+                    Ok(loaded) => loaded,
+                    Err(error) => {
+                        println!("{}", error);
+                        return false;
+                    }
+                };
+                let drawable = drawables.get_mut(component.parent).unwrap();
+                if let Some(lods) = lods {
+                    component.lod_age = Some(component.age);
+                    drawable.lods = lods;
+                }
+                if drawable.meshes.is_empty() {
+                    drawable.meshes.push((globals.default_plant_material.clone(), mesh));
+                } else {
+                    drawable.meshes[0].1 = mesh;
+                }
+            }
+            true
+        });
+    }
+}
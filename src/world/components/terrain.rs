@@ -1,8 +1,12 @@
 use ndshape::{Shape, Shape2u32};
 
-use crate::{transform::Vector3f, mesh_generation::{GenerateMesh, extrude_mesh}, triangle_draw::{TriangleMesh, TriangleDrawSystem}};
+use crate::{mesh_io::Heightmap, noise::HeightGenerator, transform::{Point3f, Transform, Vector3f}, mesh_generation::{GenerateMesh, extrude_mesh}, triangle_draw::{TriangleMesh, TriangleDrawSystem}};
 
-use super::{Globals, DrawableId, DrawableComponentList, new_component_list_type};
+use super::{Globals, DrawableId, DrawableComponentList, load_lods, new_component_list_type};
+
+// How far below its surface a patch's walls extend. This hides cracks between neighbouring patches, and makes each one look like a solid slab
+// from the side. There's no bottom, as it can't be seen from above and would double the patch's triangles.
+const SKIRT_DEPTH: f32 = 1.0;
 
 pub struct TerrainPatch {
     parent: DrawableId,
@@ -21,9 +25,66 @@ impl TerrainPatch {
             height_data: vec![0.0; size],
         }
     }
+    pub fn from_heightmap(parent: DrawableId, heightmap: Heightmap) -> TerrainPatch {
+        TerrainPatch {
+            parent,
+            dirty: true,
+            shape: heightmap.size,
+            height_data: heightmap.heights,
+        }
+    }
     pub fn parent(&self) -> DrawableId {
         self.parent
     }
+    pub fn heightmap(&self) -> Heightmap {
+        Heightmap { size: self.shape, heights: self.height_data.clone() }
+    }
+
+    // Sets every height from a generator, sampled in world coordinates so neighbouring patches line up. `transform` is the patch's
+    // transform, which must not be rotated, as create_terrain's aren't.
+    pub fn generate_heights(&mut self, generator: &HeightGenerator, transform: Transform) {
+        let shape = Shape2u32::new(self.shape);
+        for (index, height) in self.height_data.iter_mut().enumerate() {
+            let [x, z] = shape.delinearize(index as u32);
+            let world_x = transform.disp.x + x as f32 * transform.scale;
+            let world_z = transform.disp.z + z as f32 * transform.scale;
+            let world_height = generator.height(world_x as f64, world_z as f64) as f32;
+            *height = (world_height - transform.disp.y) / transform.scale;
+        }
+        self.dirty = true;
+    }
+
+    // Interpolated height at a point in the patch's local coordinates, or None if the point is outside the patch.
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let max = [(self.shape[0] - 1) as f32, (self.shape[1] - 1) as f32];
+        if !(0.0..=max[0]).contains(&x) || !(0.0..=max[1]).contains(&z) {
+            return None;
+        }
+        let shape = Shape2u32::new(self.shape);
+        let height = |x: u32, z: u32| self.height_data[shape.linearize([x, z]) as usize];
+        let x0 = (x as u32).min(self.shape[0].saturating_sub(2));
+        let z0 = (z as u32).min(self.shape[1].saturating_sub(2));
+        let x1 = (x0 + 1).min(self.shape[0] - 1);
+        let z1 = (z0 + 1).min(self.shape[1] - 1);
+        let (fx, fz) = (x - x0 as f32, z - z0 as f32);
+        let near = height(x0, z0) + (height(x1, z0) - height(x0, z0)) * fx;
+        let far = height(x0, z1) + (height(x1, z1) - height(x0, z1)) * fx;
+        Some(near + (far - near) * fz)
+    }
+}
+
+// Maps textures onto the terrain from above, one repeat per unit. The skirt's walls face sideways, so they're mapped from the side
+// they face instead.
+fn planar_uvs(mesh: &TriangleMesh) -> Vec<[f32; 2]> {
+    mesh.positions.iter().zip(mesh.normals.iter()).map(|(position, normal)| {
+        if normal[1].abs() > f32::EPSILON {
+            [position[0], position[2]]
+        } else if normal[0].abs() > normal[2].abs() {
+            [position[2], position[1]]
+        } else {
+            [position[0], position[1]]
+        }
+    }).collect()
 }
 
 impl GenerateMesh for TerrainPatch {
@@ -37,8 +98,10 @@ impl GenerateMesh for TerrainPatch {
             positions: buffer.positions,
             normals: buffer.normals,
             indices: buffer.indices,
+            ..TriangleMesh::default()
         };
-        extrude_mesh(&mut mesh, Vector3f::unit_y());
+        extrude_mesh(&mut mesh, -Vector3f::unit_y() * SKIRT_DEPTH, false);
+        mesh.uvs = planar_uvs(&mesh);
         mesh
     }
 }
@@ -46,12 +109,33 @@ impl GenerateMesh for TerrainPatch {
 new_component_list_type!(TerrainComponentList, TerrainId, TerrainPatch);
 
 impl TerrainComponentList {
+    // Height of the highest terrain patch above or below a point in world coordinates.
+    pub fn height_at(&self, drawables: &DrawableComponentList, point: Point3f) -> Option<f32> {
+        use cgmath::Transform;
+        self.0.values().filter_map(|patch| {
+            let transform = drawables.get(patch.parent)?.transform;
+            let local = transform.inverse_transform()?.transform_point(point);
+            let height = patch.height_at(local.x, local.z)?;
+            Some(transform.transform_point(Point3f::new(local.x, height, local.z)).y)
+        }).reduce(f32::max)
+    }
+
     pub fn update(&mut self, globals: &Globals, draw_system: &TriangleDrawSystem, drawables: &mut DrawableComponentList) {
         for component in self.0.values_mut() {
             if component.dirty {
                 component.dirty = false;
-                let mesh = draw_system.load_mesh(component.generate_mesh());
+                let mesh = component.generate_mesh();
+                let loaded = load_lods(draw_system, &globals.default_terrain_material, &mesh)
+                    .and_then(|lods| Ok((draw_system.load_mesh(mesh)?, lods)));
+                let (mesh, lods) = match loaded {
+                    Ok(loaded) => loaded,
+                    Err(error) => {
+                        println!("{}", error);
+                        continue;
+                    }
+                };
                 let drawable = drawables.get_mut(component.parent).unwrap();
+                drawable.lods = lods;
                 if drawable.meshes.is_empty() {
                     drawable.meshes.push((globals.default_terrain_material.clone(), mesh));
                 } else {
@@ -61,3 +145,37 @@ impl TerrainComponentList {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use cgmath::One;
+
+    use super::*;
+    use crate::transform::{Quaternion, TransformExtensions};
+
+    // World height of a patch at a point in its local coordinates.
+    fn world_height(patch: &TerrainPatch, transform: Transform, x: f32, z: f32) -> f32 {
+        transform.disp.y + patch.height_at(x, z).unwrap() * transform.scale
+    }
+
+    #[test]
+    fn neighbouring_patches_meet_at_their_border() {
+        let generator = HeightGenerator::Terrace {
+            source: Box::new(HeightGenerator::Fbm { seed: 5, octaves: 4, frequency: 0.1, amplitude: 6.0 }),
+            step: 0.5,
+        };
+        let left_transform = Transform::from_translation(Vector3f::new(-10.0, 2.0, 3.0));
+        let right_transform = Transform::new(Vector3f::new(5.0, -1.0, 3.0), Quaternion::one(), 0.5);
+        let mut left = TerrainPatch::new(DrawableId::default(), [16, 16]);
+        let mut right = TerrainPatch::new(DrawableId::default(), [16, 31]);
+        left.generate_heights(&generator, left_transform);
+        right.generate_heights(&generator, right_transform);
+        for z in 0..16 {
+            let left_height = world_height(&left, left_transform, 15.0, z as f32);
+            let right_height = world_height(&right, right_transform, 0.0, z as f32 * 2.0);
+            let expected = generator.height(5.0, 3.0 + z as f64) as f32;
+            assert!((left_height - right_height).abs() < 1e-4, "{} and {} at z {}", left_height, right_height, z);
+            assert!((left_height - expected).abs() < 1e-4, "{} instead of {} at z {}", left_height, expected, z);
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use cgmath::MetricSpace;
+use ndshape::{Shape, Shape3u32};
+
+use crate::{transform::Point3f, mesh_generation::{GenerateMesh, surface_nets}, triangle_draw::{TriangleMesh, TriangleDrawSystem}};
+
+use super::{Globals, DrawableId, DrawableComponentList, load_lods, new_component_list_type};
+
+// Terrain stored as a density field, so unlike a TerrainPatch it can have overhangs, caves and tunnels. Densities are sampled at each
+// point of the grid in the patch's local coordinates: negative is solid and positive is empty, roughly the distance to the surface.
+pub struct VolumePatch {
+    parent: DrawableId,
+    dirty: bool,
+    shape: [u32; 3],
+    density: Vec<f32>,
+}
+
+impl VolumePatch {
+    // A patch that is solid below `ground` and empty above it.
+    pub fn new(parent: DrawableId, shape: [u32; 3], ground: f32) -> VolumePatch {
+        let grid = Shape3u32::new(shape);
+        let density = (0..grid.size()).map(|index| grid.delinearize(index)[1] as f32 - ground).collect();
+        VolumePatch {
+            parent,
+            dirty: true,
+            shape,
+            density,
+        }
+    }
+    pub fn parent(&self) -> DrawableId {
+        self.parent
+    }
+
+    // Density at a grid point, or None if the point is outside the patch.
+    pub fn density_at(&self, point: [u32; 3]) -> Option<f32> {
+        if (0..3).any(|axis| point[axis] >= self.shape[axis]) {
+            return None;
+        }
+        Some(self.density[Shape3u32::new(self.shape).linearize(point) as usize])
+    }
+
+    // Adds a sphere of solid (if `fill` is set) or removes one, in the patch's local coordinates.
+    pub fn sculpt_sphere(&mut self, center: Point3f, radius: f32, fill: bool) {
+        if self.density.is_empty() {
+            return;
+        }
+        let grid = Shape3u32::new(self.shape);
+        // The grid points the sphere can affect along an axis, or None if it misses the patch.
+        let range = |axis: usize, center: f32| {
+            let min = (center - radius).floor().max(0.0);
+            let max = (center + radius).ceil().min((self.shape[axis] - 1) as f32);
+            (min <= max).then(|| min as u32..=max as u32)
+        };
+        let (xs, ys, zs) = match (range(0, center.x), range(1, center.y), range(2, center.z)) {
+            (Some(xs), Some(ys), Some(zs)) => (xs, ys, zs),
+            _ => return,
+        };
+        for z in zs {
+            for y in ys.clone() {
+                for x in xs.clone() {
+                    let distance = center.distance(Point3f::new(x as f32, y as f32, z as f32)) - radius;
+                    let density = &mut self.density[grid.linearize([x, y, z]) as usize];
+                    *density = if fill { density.min(distance) } else { density.max(-distance) };
+                    self.dirty = true;
+                }
+            }
+        }
+    }
+}
+
+impl GenerateMesh for VolumePatch {
+    fn generate_mesh(&self) -> TriangleMesh {
+        // Surrounding the field with empty samples closes the surface where the solid reaches the patch's edges.
+        let padded_shape = self.shape.map(|size| size + 2);
+        let padded = Shape3u32::new(padded_shape);
+        let grid = Shape3u32::new(self.shape);
+        let density: Vec<f32> = (0..padded.size()).map(|index| {
+            let point = padded.delinearize(index);
+            if (0..3).any(|axis| point[axis] == 0 || point[axis] > self.shape[axis]) {
+                1.0
+            } else {
+                self.density[grid.linearize(point.map(|x| x - 1)) as usize]
+            }
+        }).collect();
+        let mut mesh = surface_nets(&density, padded_shape);
+        for position in mesh.positions.iter_mut() {
+            *position = position.map(|x| x - 1.0);
+        }
+        mesh
+    }
+}
+
+new_component_list_type!(VolumeComponentList, VolumeId, VolumePatch);
+
+impl VolumeComponentList {
+    // Sculpts a sphere in world coordinates into every patch it overlaps.
+    pub fn sculpt_sphere(&mut self, drawables: &DrawableComponentList, center: Point3f, radius: f32, fill: bool) {
+        use cgmath::Transform;
+        for patch in self.0.values_mut() {
+            let transform = match drawables.get(patch.parent) {
+                Some(drawable) => drawable.transform,
+                None => continue,
+            };
+            if let Some(local) = transform.inverse_transform() {
+                patch.sculpt_sphere(local.transform_point(center), radius / transform.scale.abs(), fill);
+            }
+        }
+    }
+
+    pub fn update(&mut self, globals: &Globals, draw_system: &TriangleDrawSystem, drawables: &mut DrawableComponentList) {
+        for component in self.0.values_mut() {
+            if component.dirty {
+                component.dirty = false;
+                let mesh = component.generate_mesh();
+                let drawable = drawables.get_mut(component.parent).unwrap();
+                // Digging out every solid voxel leaves nothing to draw.
+                if mesh.indices.is_empty() {
+                    drawable.meshes.clear();
+                    drawable.lods.clear();
+                    continue;
+                }
+                let loaded = load_lods(draw_system, &globals.default_terrain_material, &mesh)
+                    .and_then(|lods| Ok((draw_system.load_mesh(mesh)?, lods)));
+                let (mesh, lods) = match loaded {
+                    Ok(loaded) => loaded,
+                    Err(error) => {
+                        println!("{}", error);
+                        continue;
+                    }
+                };
+                drawable.lods = lods;
+                if drawable.meshes.is_empty() {
+                    drawable.meshes.push((globals.default_terrain_material.clone(), mesh));
+                } else {
+                    drawable.meshes[0].1 = mesh;
+                }
+            }
+        }
+    }
+}
@@ -1,23 +1,37 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::Path};
 
-use crate::{color::Color, triangle_draw::{TriangleDrawSystem, TriangleMaterialHandle, TriangleMeshHandle, TriangleMesh}};
+use crate::{color::Color, mesh_generation::{generate_tangents, primitives}, mesh_io, triangle_draw::{TriangleDrawSystem, TriangleMaterialHandle, TriangleMeshHandle, TriangleMesh}};
 
 type Library<T> = HashMap<String, T>;
 
 pub struct AssetLibrary {
     meshes: Library<TriangleMeshHandle>,
+    // Meshes kept on the CPU, so they can be copied into generated meshes, like the surfaces of L-systems.
+    surfaces: Library<TriangleMesh>,
     materials: Library<TriangleMaterialHandle>,
+    // The material each loaded mesh was given in its file.
+    mesh_materials: Library<TriangleMaterialHandle>,
 }
 
 impl AssetLibrary {
     pub fn new() -> AssetLibrary {
         AssetLibrary {
             meshes: Library::new(),
+            surfaces: Library::new(),
             materials: Library::new(),
+            mesh_materials: Library::new(),
         }
     }
     pub fn create_standard_assets(&mut self, draw_system: &TriangleDrawSystem) {
-        self.meshes.insert("cube".to_string(), create_cube(draw_system));
+        self.add_mesh(draw_system, "cube", create_cube());
+        self.add_mesh(draw_system, "sphere", primitives::uv_sphere(24, 12));
+        self.add_mesh(draw_system, "icosphere", primitives::ico_sphere(2));
+        self.add_mesh(draw_system, "cylinder", primitives::cylinder(24));
+        self.add_mesh(draw_system, "cone", primitives::cone(24));
+        self.add_mesh(draw_system, "capsule", primitives::capsule(0.5, 24, 6));
+        self.add_mesh(draw_system, "torus", primitives::torus(0.25, 32, 12));
+        self.add_mesh(draw_system, "plane", primitives::plane(8));
+        self.surfaces.insert("leaf".to_string(), create_leaf());
         self.materials.insert("black".to_string(), draw_system.load_material(Color::rgb(0.0, 0.0, 0.0)));
         self.materials.insert("red".to_string(), draw_system.load_material(Color::rgb(1.0, 0.0, 0.0)));
         self.materials.insert("green".to_string(), draw_system.load_material(Color::rgb(0.0, 1.0, 0.0)));
@@ -25,15 +39,71 @@ impl AssetLibrary {
         self.materials.insert("white".to_string(), draw_system.load_material(Color::rgb(1.0, 1.0, 1.0)));
     }
 
+    // Loads the mesh for drawing, and keeps it as a surface too.
+    fn add_mesh(&mut self, draw_system: &TriangleDrawSystem, key: &str, mesh: TriangleMesh) {
+        let handle = draw_system.load_mesh(mesh.clone()).unwrap_or_else(|error| panic!("failed to load {} mesh: {}", key, error));
+        self.meshes.insert(key.to_string(), handle);
+        self.surfaces.insert(key.to_string(), mesh);
+    }
+
+    // Loads the meshes and materials in a mesh file, registering them as "<file stem>/<name>". Returns the names of the meshes.
+    pub fn load_file(&mut self, draw_system: &TriangleDrawSystem, path: &Path) -> std::io::Result<Vec<String>> {
+        let scene = mesh_io::load_scene(path)?;
+        let stem = path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+        let materials: Vec<TriangleMaterialHandle> = scene.materials.iter().map(|material| {
+            let [r, g, b, a] = material.color;
+            let handle = draw_system.load_material(Color::rgba(r, g, b, a));
+            self.materials.insert(format!("{}/{}", stem, material.name), handle.clone());
+            handle
+        }).collect();
+        let mut names = Vec::with_capacity(scene.meshes.len());
+        for mesh in scene.meshes {
+            let name = format!("{}/{}", stem, mesh.name);
+            let handle = draw_system.load_mesh(mesh.mesh.clone())
+                .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", name, error)))?;
+            self.meshes.insert(name.clone(), handle);
+            self.surfaces.insert(name.clone(), mesh.mesh);
+            if let Some(material) = mesh.material.and_then(|material| materials.get(material)) {
+                self.mesh_materials.insert(name.clone(), material.clone());
+            }
+            names.push(name);
+        }
+        Ok(names)
+    }
+    // Loads every mesh file in a directory, skipping files that fail to load after reporting them. A missing directory has no meshes.
+    pub fn load_directory(&mut self, draw_system: &TriangleDrawSystem, directory: &Path) {
+        let entries = match std::fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return,
+            Err(error) => {
+                println!("Couldn't read {}: {}", directory.display(), error);
+                return;
+            }
+        };
+        let mut paths: Vec<_> = entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).filter(|path| path.is_file()).collect();
+        paths.sort();
+        for path in paths {
+            if let Err(error) = self.load_file(draw_system, &path) {
+                println!("Couldn't load {}: {}", path.display(), error);
+            }
+        }
+    }
+
     pub fn get_mesh(&self, key: &str) -> Option<TriangleMeshHandle> {
         self.meshes.get(key).cloned()
     }
+    pub fn get_surface(&self, key: &str) -> Option<&TriangleMesh> {
+        self.surfaces.get(key)
+    }
     pub fn get_material(&self, key: &str) -> Option<TriangleMaterialHandle> {
         self.materials.get(key).cloned()
     }
+    pub fn get_mesh_material(&self, key: &str) -> Option<TriangleMaterialHandle> {
+        self.mesh_materials.get(key).cloned()
+    }
 }
 
-fn create_cube(draw_system: &TriangleDrawSystem) -> TriangleMeshHandle {
+fn create_cube() -> TriangleMesh {
     fn triangulate_face(indices: &mut Vec<u32>, face_indices: std::ops::Range<u32>) {
         for i in 2..(face_indices.len() as u32) {
             indices.push(face_indices.start + 0);
@@ -48,11 +118,16 @@ fn create_cube(draw_system: &TriangleDrawSystem) -> TriangleMeshHandle {
     let face_vertex_counts = [4, 4, 4, 4, 4, 4];
     let face_indices = [0, 1, 3, 2, 2, 3, 7, 6, 6, 7, 5, 4, 4, 5, 1, 0, 2, 6, 4, 0, 7, 3, 1, 5];
 
+    // Each face's corners are listed in order around it, so each face gets the whole texture.
+    let face_uvs = [[0., 0.], [1., 0.], [1., 1.], [0., 1.]];
+
     let mut positions_buffer = Vec::with_capacity(face_indices.len());
     let mut normals_buffer = Vec::with_capacity(face_indices.len());
+    let mut uvs_buffer = Vec::with_capacity(face_indices.len());
     for i in 0..face_indices.len() {
         positions_buffer.push(positions[face_indices[i]]);
         normals_buffer.push(normals[i / 4]);
+        uvs_buffer.push(face_uvs[i % 4]);
     }
 
     let mut current_index = 0;
@@ -62,9 +137,25 @@ fn create_cube(draw_system: &TriangleDrawSystem) -> TriangleMeshHandle {
         current_index += vertex_count;
     }
 
-    draw_system.load_mesh(TriangleMesh {
+    let mut mesh = TriangleMesh {
         positions: positions_buffer,
         normals: normals_buffer,
+        uvs: uvs_buffer,
         indices: indices_buffer,
-    })
+        ..TriangleMesh::default()
+    };
+    generate_tangents(&mut mesh);
+    mesh
+}
+
+// A unit length diamond in the turtle's frame, starting at the origin and pointing along +z, facing up.
+// Its UVs map it onto the unit square, with v running from base to tip.
+fn create_leaf() -> TriangleMesh {
+    TriangleMesh {
+        positions: vec![[0., 0., 0.], [-0.5, 0., 0.5], [0., 0., 1.], [0.5, 0., 0.5]],
+        normals: vec![[0., 1., 0.]; 4],
+        uvs: vec![[0.5, 0.], [1., 0.5], [0.5, 1.], [0., 0.5]],
+        indices: vec![0, 1, 2, 0, 2, 3],
+        ..TriangleMesh::default()
+    }
 }
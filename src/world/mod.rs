@@ -5,6 +5,7 @@ pub mod input;
 pub mod spellcaster;
 
 use std::collections::HashMap;
+use std::path::Path;
 use std::time::Instant;
 
 use cgmath::{Matrix4, Vector3};
@@ -26,6 +27,7 @@ struct WorldTime {
 pub struct Globals {
     player_avatar: Option<AvatarId>,
     default_terrain_material: TriangleMaterialHandle,
+    default_plant_material: TriangleMaterialHandle,
 }
 
 pub struct World {
@@ -42,7 +44,9 @@ impl World {
     pub fn new(draw_system: &TriangleDrawSystem) -> World {
         let mut assets = AssetLibrary::new();
         assets.create_standard_assets(draw_system);
+        assets.load_directory(draw_system, Path::new("input/meshes"));
         let default_terrain_material = assets.get_material("green").unwrap();
+        let default_plant_material = assets.get_material("white").unwrap();
         World {
             time: WorldTime { last_frame: Instant::now() },
             input: InputSystem::new(),
@@ -53,12 +57,14 @@ impl World {
             globals: Globals {
                 player_avatar: None,
                 default_terrain_material,
+                default_plant_material,
             },
         }
     }
     pub fn init(&mut self, draw_system: &TriangleDrawSystem) {
         let avatar = TriangleDrawable {
             meshes: Vec::new(),
+            lods: Vec::new(),
             transform: Transform::identity(),
         };
         let cube_id = self.components.drawables.add(avatar);
@@ -67,11 +73,13 @@ impl World {
         let cube_mesh = self.assets.get_mesh("cube").unwrap();
         let cube = TriangleDrawable {
             meshes: vec![(self.assets.get_material("white").unwrap(), cube_mesh.clone())],
+            lods: Vec::new(),
             transform: Transform::from_translation(Vector3::new(4.0, 0.0, 0.0)),
         };
         self.components.drawables.add(cube);
         let cube = TriangleDrawable {
             meshes: vec![(self.assets.get_material("white").unwrap(), cube_mesh.clone())],
+            lods: Vec::new(),
             transform: Transform::from_translation(Vector3::new(-4.0, 0.0, 0.0)),
         };
         self.components.drawables.add(cube);
@@ -85,7 +93,7 @@ impl World {
                 panic!("failed parsing code");
             }
         };
-        let mut spell_context = SpellContext { components: &mut self.components, globals: &mut self.globals };
+        let mut spell_context = SpellContext { components: &mut self.components, globals: &mut self.globals, assets: &self.assets };
         for item in startup_code {
             self.spellcaster.apply_value(&mut spell_context, item);
         }
@@ -107,7 +115,7 @@ impl World {
         self.components.update(&self.globals, draw_system, delta_time);
 
         // cast spells
-        let mut spell_context = SpellContext { components: &mut self.components, globals: &mut self.globals };
+        let mut spell_context = SpellContext { components: &mut self.components, globals: &mut self.globals, assets: &self.assets };
         for binding in self.input.player().spells().get_spellcasts() {
             self.spellcaster.cast_bound_spell(&mut spell_context, binding);
         }
@@ -124,6 +132,6 @@ impl World {
     }
     pub fn render(&mut self, renderer: &mut TriangleDraw) {
         renderer.set_camera(self.camera.frame_data());
-        self.components.render(renderer);
+        self.components.render(renderer, self.camera.camera_position(), &self.camera.frustum());
     }
 }
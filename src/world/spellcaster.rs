@@ -3,7 +3,7 @@ use cgmath::EuclideanSpace;
 
 use crate::{code::{Value, spell::*}, transform::{Vector3f, Point3f}};
 
-use super::{Globals, components::ComponentSystem};
+use super::{Globals, components::ComponentSystem, library::AssetLibrary};
 
 #[derive(Default)]
 pub struct Spellcaster {
@@ -13,6 +13,7 @@ pub struct Spellcaster {
 pub struct SpellContext<'a> {
     pub components: &'a mut ComponentSystem,
     pub globals: &'a mut Globals,
+    pub assets: &'a AssetLibrary,
 }
 
 impl Spellcaster {